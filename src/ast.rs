@@ -1,6 +1,11 @@
 use markdown::mdast::{
-    Definition, FootnoteDefinition, FootnoteReference, Heading, Html, Link, LinkReference, Node,
-    Text,
+    Definition, FootnoteDefinition, FootnoteReference, Heading, Html, Image, InlineCode,
+    InlineMath, Link, LinkReference, Node, Table, Text,
+};
+
+use crate::{
+    links::{url_decode, MdLink},
+    slug::{heading_slug, heading_slugs, slug},
 };
 
 pub struct AstIterator<'a> {
@@ -33,8 +38,9 @@ pub trait TraverseNode {
     // fn find_nodes<T, F>(&self, extractor: F) -> Vec<&T>
     // where
     //     F: Fn(&Node) -> Option<&T>;
-    fn find_linkable_for_position(&self, line: u32, character: u32) -> Option<&Node>;
-    fn find_definition_for_position(&self, line: u32, character: u32) -> Option<&Node>;
+    fn find_linkable_for_position(&self, offset: usize) -> Option<&Node>;
+    fn find_definition_for_position(&self, offset: usize) -> Option<&Node>;
+    fn find_table_for_position(&self, offset: usize) -> Option<&Table>;
     fn find_heading_for_link(&self, link: &Link) -> Option<&Heading>;
     fn find_heading_for_link_identifier(&self, link: &str) -> Option<&Heading>;
     fn find_definition_for_identifier(&self, identifier: &str) -> Option<&Definition>;
@@ -43,6 +49,7 @@ pub trait TraverseNode {
     fn find_footnote_references_for_identifier(&self, identifier: &str) -> Vec<&FootnoteReference>;
     fn find_headings(&self) -> Vec<&Heading>;
     fn find_links(&self) -> Vec<&Link>;
+    fn find_images(&self) -> Vec<&Image>;
     fn find_defintions(&self) -> Vec<&Definition>;
     fn find_link_references(&self) -> Vec<&LinkReference>;
     fn find_footnote_definitions(&self) -> Vec<&FootnoteDefinition>;
@@ -68,17 +75,14 @@ impl TraverseNode for Node {
     //     self.ast_iter().filter_map(extractor).collect()
     // }
 
-    fn find_linkable_for_position(&self, line: u32, character: u32) -> Option<&Node> {
+    fn find_linkable_for_position(&self, offset: usize) -> Option<&Node> {
         self.ast_iter().find(|node| match node {
             Node::Heading(Heading { position, .. })
             | Node::Link(Link { position, .. })
             | Node::LinkReference(LinkReference { position, .. })
             | Node::FootnoteReference(FootnoteReference { position, .. }) => {
                 if let Some(pos) = position {
-                    (line + 1) as usize >= pos.start.line
-                        && (line + 1) as usize <= pos.end.line
-                        && (character + 1) as usize >= pos.start.column
-                        && (character + 1) as usize <= pos.end.column
+                    offset >= pos.start.offset && offset <= pos.end.offset
                 } else {
                     false
                 }
@@ -86,16 +90,13 @@ impl TraverseNode for Node {
             _ => false,
         })
     }
-    fn find_definition_for_position(&self, line: u32, character: u32) -> Option<&Node> {
+    fn find_definition_for_position(&self, offset: usize) -> Option<&Node> {
         self.ast_iter().find(|node| match node {
             Node::Heading(Heading { position, .. })
             | Node::Definition(Definition { position, .. })
             | Node::FootnoteDefinition(FootnoteDefinition { position, .. }) => {
                 if let Some(pos) = position {
-                    (line + 1) as usize >= pos.start.line
-                        && (line + 1) as usize <= pos.end.line
-                        && (character + 1) as usize >= pos.start.column
-                    // && (character + 1) as usize <= pos.end.column
+                    offset >= pos.start.offset && offset <= pos.end.offset
                 } else {
                     false
                 }
@@ -104,44 +105,23 @@ impl TraverseNode for Node {
         })
     }
 
-    fn find_heading_for_link(&self, link: &Link) -> Option<&Heading> {
-        let target = link.url.replace('#', "");
-        let normalized_target = target.to_lowercase().replace(' ', "-");
-
-        self.ast_iter().find_map(|node| {
-            let Node::Heading(heading) = node else {
-                return None;
-            };
-            let Node::Text(Text { value, .. }) = heading.children.first()? else {
-                return None;
-            };
-
-            if value == &target || value.to_lowercase().replace(' ', "-") == normalized_target {
-                Some(heading)
-            } else {
-                None
-            }
+    fn find_table_for_position(&self, offset: usize) -> Option<&Table> {
+        self.ast_iter().find_map(|node| match node {
+            Node::Table(table) => table
+                .position
+                .as_ref()
+                .is_some_and(|pos| offset >= pos.start.offset && offset <= pos.end.offset)
+                .then_some(table),
+            _ => None,
         })
     }
 
+    fn find_heading_for_link(&self, link: &Link) -> Option<&Heading> {
+        find_heading_for_target(self, &link.url)
+    }
+
     fn find_heading_for_link_identifier(&self, link_identifier: &str) -> Option<&Heading> {
-        let target = link_identifier.replace('#', "");
-        let normalized_target = target.to_lowercase().replace(' ', "-");
-
-        self.ast_iter().find_map(|node| {
-            let Node::Heading(heading) = node else {
-                return None;
-            };
-            let Node::Text(Text { value, .. }) = heading.children.first()? else {
-                return None;
-            };
-
-            if value == &target || value.to_lowercase().replace(' ', "-") == normalized_target {
-                Some(heading)
-            } else {
-                None
-            }
-        })
+        find_heading_for_target(self, link_identifier)
     }
 
     fn find_definition_for_identifier(&self, identifier: &str) -> Option<&Definition> {
@@ -194,6 +174,15 @@ impl TraverseNode for Node {
             .collect()
     }
 
+    fn find_images(&self) -> Vec<&Image> {
+        self.ast_iter()
+            .filter_map(|node| match node {
+                Node::Image(image) => Some(image),
+                _ => None,
+            })
+            .collect()
+    }
+
     fn find_defintions(&self) -> Vec<&Definition> {
         self.ast_iter()
             .filter_map(|node| match node {
@@ -267,13 +256,49 @@ impl TraverseNode for Node {
     }
 }
 
-pub fn get_heading_text(heading: &Heading) -> Option<&str> {
-    for child in &heading.children {
-        if let Node::Text(Text { value, .. }) = child {
-            return Some(value);
-        };
-    }
-    None
+/// Heading matching the fragment `target`, either by its unique slug
+/// (`#setup-1`) or by its text (`#Setup`)
+fn find_heading_for_target<'a>(ast: &'a Node, target: &str) -> Option<&'a Heading> {
+    let target = url_decode(target.trim_start_matches('#'));
+    let heading_slugs = heading_slugs(ast);
+    heading_slugs
+        .iter()
+        .find(|(_, heading_slug)| *heading_slug == target)
+        .or_else(|| {
+            let target_slug = slug(&target);
+            heading_slugs
+                .iter()
+                .find(|(heading, _)| heading_slug(heading) == target_slug)
+        })
+        .map(|(heading, _)| *heading)
+}
+
+/// Plain text of inline nodes, like the text of a heading
+pub fn inline_text(children: &[Node]) -> String {
+    children.iter().fold(String::new(), |mut acc, child| {
+        match child {
+            Node::Text(Text { value, .. })
+            | Node::InlineCode(InlineCode { value, .. })
+            | Node::InlineMath(InlineMath { value, .. }) => acc.push_str(value),
+            Node::Image(Image { alt, .. }) => acc.push_str(alt),
+            // the source of wikilinks is already contained in the text nodes
+            Node::Link(link) if MdLink::new(link).is_wikilink() => {}
+            Node::Html(_) => {}
+            node => {
+                if let Some(children) = node.children() {
+                    acc.push_str(&inline_text(children));
+                }
+            }
+        }
+        acc
+    })
+}
+
+/// Text of `heading` with all inline formatting flattened
+pub fn get_heading_text(heading: &Heading) -> Option<String> {
+    let text = inline_text(&heading.children);
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
 }
 
 #[cfg(test)]
@@ -284,25 +309,33 @@ mod tests {
     use markdown::mdast::{Node, ReferenceKind};
     use markdown::unist::{Point, Position};
 
+    use crate::line_index::{LineIndex, PositionEncoding};
     use crate::links::parse_wiki_links;
 
+    fn markdown() -> String {
+        std::fs::read_to_string(r#"testdata/test file c.md"#).expect("test file does not exists")
+    }
+
     fn ast() -> Node {
-        let markdown = std::fs::read_to_string(r#"testdata/test file c.md"#)
-            .expect("test file does not exists");
+        let markdown = markdown();
         let mut ast = markdown::to_mdast(&markdown, &markdown::ParseOptions::gfm())
             .expect("markdown can't be parsed");
-        parse_wiki_links(&mut ast);
+        parse_wiki_links(&mut ast, &markdown);
         ast
     }
 
+    fn offset(line: u32, character: u32) -> usize {
+        let markdown = markdown();
+        let line_index = LineIndex::new(&markdown, PositionEncoding::Utf16);
+        line_index.offset(&markdown, &lsp_types::Position { line, character })
+    }
+
     #[test]
     fn test_find_linkable_for_position() {
         let ast = ast();
         let line_number = 31;
-        // let linkable = find_linkable_for_position(&ast, line_number, 0);
-        // let linkable_2 = find_linkable_for_position(&ast, line_number, 11);
-        let linkable = ast.find_linkable_for_position(line_number, 0);
-        let linkable_2 = ast.find_linkable_for_position(line_number, 11);
+        let linkable = ast.find_linkable_for_position(offset(line_number, 0));
+        let linkable_2 = ast.find_linkable_for_position(offset(line_number, 11));
         insta::assert_debug_snapshot!(linkable);
         insta::assert_debug_snapshot!(linkable_2);
     }
@@ -311,8 +344,8 @@ mod tests {
     fn test_find_definition_for_position() {
         let ast = ast();
         let line_number = 31;
-        let linkable = ast.find_definition_for_position(line_number, 0);
-        let linkable_2 = ast.find_definition_for_position(line_number, 11);
+        let linkable = ast.find_definition_for_position(offset(line_number, 0));
+        let linkable_2 = ast.find_definition_for_position(offset(line_number, 11));
         insta::assert_debug_snapshot!(linkable);
         insta::assert_debug_snapshot!(linkable_2);
     }
@@ -363,6 +396,22 @@ mod tests {
         insta::assert_debug_snapshot!(found_heading);
     }
 
+    #[test]
+    fn test_find_heading_for_duplicate_slugs() {
+        let md = "# Setup\n\n## What's `new`?\n\n# Setup\n";
+        let ast = markdown::to_mdast(md, &markdown::ParseOptions::gfm()).unwrap();
+        let heading_line = |target: &str| {
+            ast.find_heading_for_link_identifier(target)
+                .and_then(|heading| heading.position.as_ref())
+                .map(|pos| pos.start.line)
+        };
+        assert_eq!(heading_line("#setup"), Some(1));
+        assert_eq!(heading_line("#setup-1"), Some(5));
+        assert_eq!(heading_line("#whats-new"), Some(3));
+        assert_eq!(heading_line("#What's new?"), Some(3));
+        assert_eq!(heading_line("#setup-2"), None);
+    }
+
     #[test]
     fn test_find_definition_for_identifier() {
         let ast = ast();
@@ -473,7 +522,26 @@ mod tests {
             }),
             depth: 1,
         };
-        assert_eq!(get_heading_text(&heading), Some("Heading 1"));
+        assert_eq!(get_heading_text(&heading), Some("Heading 1".to_string()));
+    }
+
+    #[test]
+    fn test_get_heading_text_with_formatting() {
+        let md = "## The `State` struct\n\n## **Important** [notes](#x) and [[page]]\n";
+        let mut ast = markdown::to_mdast(md, &markdown::ParseOptions::gfm()).unwrap();
+        parse_wiki_links(&mut ast, md);
+        let heading_texts: Vec<Option<String>> = ast
+            .find_headings()
+            .into_iter()
+            .map(get_heading_text)
+            .collect();
+        assert_eq!(
+            heading_texts,
+            vec![
+                Some("The State struct".to_string()),
+                Some("Important notes and [[page]]".to_string())
+            ]
+        );
     }
 
     #[test]
@@ -1,9 +1,31 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
-use lsp_types::{CodeAction, CodeActionParams, Position, Range, TextEdit, Url, WorkspaceEdit};
-use markdown::mdast::{Heading, Html};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionParams, CreateFile, CreateFileOptions, Diagnostic,
+    DocumentChangeOperation, DocumentChanges, NumberOrString, OneOf,
+    OptionalVersionedTextDocumentIdentifier, Position, Range, ResourceOp, TextDocumentEdit,
+    TextEdit, Url, WorkspaceEdit,
+};
+use markdown::{
+    mdast::{AlignKind, Definition, Heading, Html, Link, LinkReference, Node},
+    unist::Position as AstPosition,
+};
+use serde::{Deserialize, Serialize};
 
-use crate::{ast::TraverseNode, rename::get_text_child, state::State};
+use crate::{
+    ast::{get_heading_text, inline_text, TraverseNode},
+    diagnostics::{check_links, unused_definitions, BrokenLinkKind},
+    links::{
+        link_target_offsets, relative_link_path, resolve_link, resolve_link_path,
+        shortest_wikilink_target, url_decode, url_encode, MdLink, ResolvedLink,
+    },
+    slug::{heading_slug, heading_slugs, slug, Slugger},
+    state::{path_from_root, MdFile, State},
+    table::{cell_at_offset, format_table, table_prefix, Table},
+};
 
 const TOC_START: &str = "<!--toc:start-->";
 const TOC_END: &str = "<!--toc:end-->";
@@ -14,38 +36,1152 @@ pub fn code_actions(params: &CodeActionParams, state: &State) -> Option<Vec<Code
 
     let mut code_actions = Vec::new();
     let ast = state.ast_for_uri(req_uri)?;
-    let headings = ast.find_headings();
-    let toc_tags: Vec<&Html> = ast
-        .find_html_nodes()
+    // the table of contents actions have no kind
+    if params.context.only.is_none() {
+        let headings = ast.find_headings();
+        let toc_tags: Vec<&Html> = ast
+            .find_html_nodes()
+            .into_iter()
+            .filter(|html| html.value == TOC_START || html.value == TOC_END)
+            .collect();
+
+        if toc_tags.is_empty() {
+            code_actions.extend(create_toc(&headings, req_uri));
+        } else {
+            code_actions.extend(update_toc(&headings, &toc_tags, req_uri));
+        }
+    }
+    let quick_fixes = is_requested(params, &CodeActionKind::QUICKFIX);
+    let refactorings = is_requested(params, &CodeActionKind::REFACTOR_REWRITE);
+    let source_actions = is_requested(params, &CodeActionKind::SOURCE);
+    if quick_fixes {
+        code_actions.extend(disambiguate_link_actions(params, state));
+        code_actions.extend(delete_unused_definition_actions(params, state));
+        code_actions.extend(broken_link_actions(params, state));
+    }
+    if refactorings {
+        code_actions.extend(table_actions(params, state));
+        code_actions.extend(convert_link_actions(params, state));
+        code_actions.extend(reference_link_actions(params, state));
+    }
+    if source_actions {
+        code_actions.extend(convert_all_wikilinks_actions(params, state));
+        code_actions.extend(convert_all_reference_links_actions(params, state));
+    }
+
+    Some(code_actions)
+}
+
+/// Fills in the edit of a code action returned without one, see
+/// `LazyEdit`
+pub fn resolve_code_action(mut code_action: CodeAction, state: &State) -> CodeAction {
+    let lazy_edit = code_action
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<LazyEdit>(data).ok());
+    if let (None, Some(lazy_edit)) = (&code_action.edit, lazy_edit) {
+        code_action.edit = Some(WorkspaceEdit {
+            changes: Some(lazy_edit.changes(state).unwrap_or_default()),
+            document_changes: None,
+            change_annotations: None,
+        });
+    }
+    code_action
+}
+
+/// Document- and workspace-wide conversions of links, only computed when the
+/// client resolves the code action (`codeAction/resolve`)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "edit", rename_all = "camelCase")]
+enum LazyEdit {
+    /// Wikilinks of the document to markdown links
+    Wikilinks { uri: Url },
+    /// Wikilinks of all documents to markdown links
+    WorkspaceWikilinks { uri: Url },
+    /// Inline links of the document to reference links
+    ReferenceLinks { uri: Url },
+    /// Reference links of the document to inline links
+    InlineLinks { uri: Url },
+}
+
+impl LazyEdit {
+    /// `None` if nothing would change
+    fn changes(&self, state: &State) -> Option<HashMap<Url, Vec<TextEdit>>> {
+        let changes: HashMap<Url, Vec<TextEdit>> = match self {
+            LazyEdit::Wikilinks { uri } => {
+                let edits = convert_wikilinks_edits(state.md_files.get(uri)?, uri, state);
+                HashMap::from([(uri.clone(), edits)])
+            }
+            LazyEdit::WorkspaceWikilinks { uri } => {
+                let changes: HashMap<Url, Vec<TextEdit>> = state
+                    .md_files
+                    .iter()
+                    .map(|(uri, md_file)| {
+                        (uri.clone(), convert_wikilinks_edits(md_file, uri, state))
+                    })
+                    .filter(|(_, edits)| !edits.is_empty())
+                    .collect();
+                // only useful if it changes other documents, too
+                if changes.keys().all(|other| other == uri) {
+                    return None;
+                }
+                changes
+            }
+            LazyEdit::ReferenceLinks { uri } => {
+                let md_file = state.md_files.get(uri)?;
+                let edits = reference_links_edits(md_file, &md_file.ast.find_links())?;
+                HashMap::from([(uri.clone(), edits)])
+            }
+            LazyEdit::InlineLinks { uri } => {
+                let edits = inline_links_edits(state.md_files.get(uri)?);
+                HashMap::from([(uri.clone(), edits)])
+            }
+        };
+        changes
+            .values()
+            .any(|edits| !edits.is_empty())
+            .then_some(changes)
+    }
+}
+
+/// Source action with the edit of `lazy_edit`, which is left to
+/// `codeAction/resolve` if the client supports it
+fn lazy_code_action(title: &str, lazy_edit: LazyEdit, state: &State) -> Option<CodeAction> {
+    if !state.code_action_resolve() {
+        let changes = lazy_edit.changes(state)?;
+        return Some(workspace_code_action(
+            title.to_string(),
+            CodeActionKind::SOURCE,
+            changes,
+        ));
+    }
+    Some(CodeAction {
+        title: title.to_string(),
+        kind: Some(CodeActionKind::SOURCE),
+        diagnostics: None,
+        edit: None,
+        command: None,
+        is_preferred: None,
+        disabled: None,
+        data: serde_json::to_value(lazy_edit).ok(),
+    })
+}
+
+/// Quick fixes deleting unused `Definition`s and `FootnoteDefinition`s in the
+/// requested range
+fn delete_unused_definition_actions(params: &CodeActionParams, state: &State) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    unused_definitions(&md_file.ast)
+        .into_iter()
+        .filter_map(|node| {
+            let (kind, label) = match node {
+                Node::Definition(def) => (
+                    BrokenLinkKind::UnusedDefinition,
+                    def.label.as_deref().unwrap_or(&def.identifier),
+                ),
+                Node::FootnoteDefinition(def) => (
+                    BrokenLinkKind::UnusedFootnoteDefinition,
+                    def.label.as_deref().unwrap_or(&def.identifier),
+                ),
+                _ => return None,
+            };
+            let pos = node.position()?;
+            let def_range = md_file.range(pos);
+            if !overlaps_requested_range(params, &def_range) {
+                return None;
+            }
+            let mut code_action = quick_fix(
+                format!("Delete unused definition `{label}`"),
+                req_uri,
+                vec![delete_definition_edit(md_file, pos)],
+                context_diagnostics(params, &def_range, kind),
+            );
+            code_action.is_preferred = Some(true);
+            Some(code_action)
+        })
+        .collect()
+}
+
+/// Refactorings converting the wikilinks in the requested range to markdown
+/// links and the markdown links to wikilinks
+fn convert_link_actions(params: &CodeActionParams, state: &State) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    md_file
+        .ast
+        .find_links()
+        .into_iter()
+        .filter_map(|link| {
+            let pos = link.position.as_ref()?;
+            let range = md_file.range(pos);
+            if !overlaps_requested_range(params, &range) {
+                return None;
+            }
+            let (title, new_text) = match MdLink::new(link) {
+                MdLink::WikiLink(_) => (
+                    "Convert to markdown link",
+                    markdown_link_for_wikilink(link, md_file, req_uri, state)?,
+                ),
+                MdLink::NormalLink(_) => (
+                    "Convert to wikilink",
+                    wikilink_for_markdown_link(link, md_file, req_uri, state)?,
+                ),
+            };
+            Some(code_action(
+                title.to_string(),
+                CodeActionKind::REFACTOR_REWRITE,
+                req_uri,
+                vec![TextEdit { range, new_text }],
+            ))
+        })
+        .collect()
+}
+
+/// Source actions converting all wikilinks of the document or of the whole
+/// workspace to markdown links
+fn convert_all_wikilinks_actions(params: &CodeActionParams, state: &State) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let has_wikilinks = |md_file: &MdFile| {
+        md_file
+            .ast
+            .find_links()
+            .into_iter()
+            .any(|link| MdLink::new(link).is_wikilink())
+    };
+    let mut code_actions = Vec::new();
+    if state.md_files.get(req_uri).is_some_and(has_wikilinks) {
+        code_actions.extend(lazy_code_action(
+            "Convert all wikilinks to markdown links",
+            LazyEdit::Wikilinks {
+                uri: req_uri.clone(),
+            },
+            state,
+        ));
+    }
+    if state
+        .md_files
+        .iter()
+        .any(|(uri, md_file)| uri != req_uri && has_wikilinks(md_file))
+    {
+        code_actions.extend(lazy_code_action(
+            "Convert all wikilinks in the workspace to markdown links",
+            LazyEdit::WorkspaceWikilinks {
+                uri: req_uri.clone(),
+            },
+            state,
+        ));
+    }
+    code_actions
+}
+
+/// Edits replacing all resolvable wikilinks in `md_file` with markdown links
+fn convert_wikilinks_edits(md_file: &MdFile, req_uri: &Url, state: &State) -> Vec<TextEdit> {
+    md_file
+        .ast
+        .find_links()
+        .into_iter()
+        .filter(|link| MdLink::new(link).is_wikilink())
+        .filter_map(|link| {
+            Some(TextEdit {
+                range: md_file.range(link.position.as_ref()?),
+                new_text: markdown_link_for_wikilink(link, md_file, req_uri, state)?,
+            })
+        })
+        .collect()
+}
+
+/// `[alias](relative/path.md#slug)` for the wikilink `link` in `md_file`,
+/// `None` if it can't be resolved
+fn markdown_link_for_wikilink(
+    link: &Link,
+    md_file: &MdFile,
+    req_uri: &Url,
+    state: &State,
+) -> Option<String> {
+    let text = match children_source(&link.children, md_file) {
+        "" => link.url.as_str(),
+        alias => alias,
+    };
+    let (file_uri, fragment) = match resolve_link(link, req_uri, state) {
+        ResolvedLink::InternalHeading {
+            file_uri, heading, ..
+        } => {
+            return Some(format!(
+                "[{text}](#{})",
+                unique_slug(file_uri, heading, state)?
+            ))
+        }
+        ResolvedLink::ExternalHeading {
+            file_uri, heading, ..
+        } => (file_uri, Some(unique_slug(file_uri, heading, state)?)),
+        // keep a link to a missing heading
+        ResolvedLink::File { file_uri, .. } => (
+            file_uri,
+            link.url.split_once('#').map(|(_, heading)| slug(heading)),
+        ),
+        _ => return None,
+    };
+    let from_dir = req_uri.to_file_path().ok()?.parent()?.to_path_buf();
+    let path = relative_link_path(&from_dir, &file_uri.to_file_path().ok()?);
+    let target = match fragment {
+        Some(fragment) => format!("{}#{fragment}", url_encode(&path)),
+        None => url_encode(&path),
+    };
+    Some(format!("[{text}]({target})"))
+}
+
+/// `[[target#Heading|text]]` for the markdown link `link` in `md_file`, `None`
+/// if it doesn't link to a markdown file in the workspace
+fn wikilink_for_markdown_link(
+    link: &Link,
+    md_file: &MdFile,
+    req_uri: &Url,
+    state: &State,
+) -> Option<String> {
+    let target = match resolve_link(link, req_uri, state) {
+        ResolvedLink::InternalHeading {
+            file_uri, heading, ..
+        } => format!("#{}", wikilink_heading(file_uri, heading, state)?),
+        ResolvedLink::ExternalHeading {
+            file_uri, heading, ..
+        } => format!(
+            "{}#{}",
+            shortest_wikilink_target(file_uri, req_uri, state)?,
+            wikilink_heading(file_uri, heading, state)?
+        ),
+        ResolvedLink::File { file_uri, .. } => {
+            let file = shortest_wikilink_target(file_uri, req_uri, state)?;
+            match link.url.split_once('#') {
+                Some((_, heading)) => format!("{file}#{}", url_decode(heading)),
+                None => file,
+            }
+        }
+        _ => return None,
+    };
+    let text = children_source(&link.children, md_file);
+    if text.is_empty() || text == target {
+        return Some(format!("[[{target}]]"));
+    }
+    if text.contains(['[', ']', '|', '\n']) {
+        return None;
+    }
+    // `|` separates cells in tables
+    let separator = match link
+        .position
+        .as_ref()
+        .and_then(|pos| md_file.ast.find_table_for_position(pos.start.offset))
+    {
+        Some(_) => "\\|",
+        None => "|",
+    };
+    Some(format!("[[{target}{separator}{text}]]"))
+}
+
+/// Source of inline nodes like the text of a link, keeping inline formatting
+fn children_source<'a>(children: &[Node], md_file: &'a MdFile) -> &'a str {
+    let start = children
+        .first()
+        .and_then(|child| child.position())
+        .map(|pos| pos.start.offset);
+    let end = children
+        .last()
+        .and_then(|child| child.position())
+        .map(|pos| pos.end.offset);
+    match start.zip(end) {
+        Some((start, end)) => md_file.buffer().get(start..end).unwrap_or_default(),
+        None => "",
+    }
+}
+
+/// Refactorings converting the links in the requested range to reference
+/// links and the reference links to inline links
+fn reference_link_actions(params: &CodeActionParams, state: &State) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    let in_range = |pos: Option<&AstPosition>| {
+        pos.is_some_and(|pos| overlaps_requested_range(params, &md_file.range(pos)))
+    };
+    let mut code_actions = Vec::new();
+    for link in md_file.ast.find_links() {
+        if !in_range(link.position.as_ref()) {
+            continue;
+        }
+        let Some(text_edits) = reference_links_edits(md_file, &[link]) else {
+            continue;
+        };
+        code_actions.push(code_action(
+            "Convert to reference link".to_string(),
+            CodeActionKind::REFACTOR_REWRITE,
+            req_uri,
+            text_edits,
+        ));
+    }
+    for link_ref in md_file.ast.find_link_references() {
+        if !in_range(link_ref.position.as_ref()) {
+            continue;
+        }
+        let Some(text_edit) = inline_link_edit(md_file, link_ref) else {
+            continue;
+        };
+        code_actions.push(code_action(
+            "Convert to inline link".to_string(),
+            CodeActionKind::REFACTOR_REWRITE,
+            req_uri,
+            vec![text_edit],
+        ));
+    }
+    code_actions
+}
+
+/// Source actions converting all links of the document to reference links and
+/// all reference links to inline links
+fn convert_all_reference_links_actions(
+    params: &CodeActionParams,
+    state: &State,
+) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    let mut code_actions = Vec::new();
+    if md_file
+        .ast
+        .find_links()
         .into_iter()
-        .filter(|html| html.value == TOC_START || html.value == TOC_END)
+        .any(|link| has_destination(link, md_file))
+    {
+        code_actions.extend(lazy_code_action(
+            "Convert all links to reference links",
+            LazyEdit::ReferenceLinks {
+                uri: req_uri.clone(),
+            },
+            state,
+        ));
+    }
+    if !md_file.ast.find_link_references().is_empty() {
+        code_actions.extend(lazy_code_action(
+            "Convert all reference links to inline links",
+            LazyEdit::InlineLinks {
+                uri: req_uri.clone(),
+            },
+            state,
+        ));
+    }
+    code_actions
+}
+
+/// Edits replacing all reference links of `md_file` with inline links and
+/// deleting the definitions only used by them
+fn inline_links_edits(md_file: &MdFile) -> Vec<TextEdit> {
+    let link_refs = md_file.ast.find_link_references();
+    let mut text_edits: Vec<TextEdit> = link_refs
+        .iter()
+        .filter_map(|link_ref| inline_link_edit(md_file, link_ref))
+        .collect();
+    if text_edits.is_empty() {
+        return text_edits;
+    }
+    // definitions only used by the inlined links are deleted
+    let inlined: HashSet<&str> = link_refs
+        .iter()
+        .map(|link_ref| link_ref.identifier.as_str())
+        .collect();
+    let images: HashSet<&str> = md_file
+        .ast
+        .ast_iter()
+        .filter_map(|node| match node {
+            Node::ImageReference(image_ref) => Some(image_ref.identifier.as_str()),
+            _ => None,
+        })
+        .collect();
+    text_edits.extend(
+        md_file
+            .ast
+            .find_defintions()
+            .into_iter()
+            .filter(|def| {
+                inlined.contains(def.identifier.as_str())
+                    && !images.contains(def.identifier.as_str())
+            })
+            .filter_map(|def| Some(delete_definition_edit(md_file, def.position.as_ref()?))),
+    );
+    text_edits.sort_by_key(|text_edit| text_edit.range.start);
+    text_edits
+}
+
+/// Autolinks and wikilinks don't have a destination that can be moved to a
+/// definition
+fn has_destination(link: &Link, md_file: &MdFile) -> bool {
+    !MdLink::new(link).is_wikilink()
+        && link
+            .position
+            .as_ref()
+            .is_some_and(|pos| md_file.buffer()[pos.start.offset..].starts_with('['))
+}
+
+/// Edits replacing `links` with reference links and adding their definitions
+/// at the end of the document. Existing definitions with the same URL and
+/// title are reused. `None` if none of the links can be converted.
+fn reference_links_edits(md_file: &MdFile, links: &[&Link]) -> Option<Vec<TextEdit>> {
+    let definitions = md_file.ast.find_defintions();
+    let mut labels: HashSet<String> = definitions
+        .iter()
+        .map(|def| def.identifier.to_lowercase())
         .collect();
+    // destination and title of the new definitions, with their labels
+    let mut new_definitions: Vec<(String, Option<String>, String)> = Vec::new();
+    let mut text_edits = Vec::new();
+    for link in links {
+        let md_link = MdLink::new(link);
+        let Some(pos) = link.position.as_ref() else {
+            continue;
+        };
+        if !has_destination(link, md_file) {
+            continue;
+        }
+        let Some((start, end)) = link_target_offsets(&md_link, md_file.buffer()) else {
+            continue;
+        };
+        let destination = &md_file.buffer()[start..end];
+        let existing = definitions
+            .iter()
+            .find(|def| def.url == link.url && def.title == link.title)
+            .map(|def| definition_label(def).to_string())
+            .or_else(|| {
+                new_definitions
+                    .iter()
+                    .find(|(dest, title, _)| dest == destination && *title == link.title)
+                    .map(|(_, _, label)| label.clone())
+            });
+        let label = match existing {
+            Some(label) => label,
+            None => {
+                let label = unique_label(&inline_text(&link.children), &mut labels);
+                new_definitions.push((destination.to_string(), link.title.clone(), label.clone()));
+                label
+            }
+        };
+        let text = children_source(&link.children, md_file);
+        // collapsed reference `[text][]` if the text matches the label
+        let new_text = match text.to_lowercase() == label.to_lowercase() {
+            true => format!("[{text}][]"),
+            false => format!("[{text}][{label}]"),
+        };
+        text_edits.push(TextEdit {
+            range: md_file.range(pos),
+            new_text,
+        });
+    }
+    if text_edits.is_empty() {
+        return None;
+    }
+    if !new_definitions.is_empty() {
+        let definitions: String = new_definitions
+            .into_iter()
+            .map(|(destination, title, label)| {
+                let title = match title {
+                    Some(title) => format!(" \"{}\"", title.replace('"', "\\\"")),
+                    None => String::new(),
+                };
+                format!("[{label}]: {}{title}\n", link_destination(&destination))
+            })
+            .collect();
+        text_edits.push(append_edit(md_file, &definitions, true));
+    }
+    Some(text_edits)
+}
 
-    if toc_tags.is_empty() {
-        code_actions.push(create_toc(&headings, req_uri));
+/// Edit appending the lines `text` to the end of `md_file`, separated from the
+/// content by a blank line. With `join_definitions` no blank line is added if
+/// the document ends with a definition.
+fn append_edit(md_file: &MdFile, text: &str, join_definitions: bool) -> TextEdit {
+    let buffer = md_file.buffer();
+    let content_end = buffer.trim_end().len();
+    let ends_with_definition = md_file
+        .ast
+        .children()
+        .and_then(|children| children.last())
+        .is_some_and(|node| matches!(node, Node::Definition(_) | Node::FootnoteDefinition(_)));
+    let separator = match content_end {
+        0 => "",
+        _ if join_definitions && ends_with_definition => "\n",
+        _ => "\n\n",
+    };
+    TextEdit {
+        range: md_file.range_from_offsets(content_end, buffer.len()),
+        new_text: format!("{separator}{text}"),
+    }
+}
+
+/// Edit replacing `link_ref` with an inline link to the URL of its definition
+fn inline_link_edit(md_file: &MdFile, link_ref: &LinkReference) -> Option<TextEdit> {
+    let def = md_file.ast.find_def_for_link_ref(link_ref)?;
+    let text = children_source(&link_ref.children, md_file);
+    let title = match &def.title {
+        Some(title) => format!(" \"{}\"", title.replace('"', "\\\"")),
+        None => String::new(),
+    };
+    Some(TextEdit {
+        range: md_file.range(link_ref.position.as_ref()?),
+        new_text: format!("[{text}]({}{title})", link_destination(&def.url)),
+    })
+}
+
+/// Label of `def` as written in the document
+fn definition_label(def: &Definition) -> &str {
+    def.label.as_deref().unwrap_or(&def.identifier)
+}
+
+/// Label for a new definition of a link with the text `text`, made unique
+/// among `labels` with a numeric suffix
+fn unique_label(text: &str, labels: &mut HashSet<String>) -> String {
+    let base = match slug(text) {
+        slug if slug.is_empty() => "link".to_string(),
+        slug => slug,
+    };
+    let mut label = base.clone();
+    let mut count = 0;
+    while labels.contains(&label) {
+        count += 1;
+        label = format!("{base}-{count}");
+    }
+    labels.insert(label.clone());
+    label
+}
+
+/// Link destination, enclosed in `<>` if it is empty or contains whitespace
+fn link_destination(url: &str) -> String {
+    match url.is_empty() || url.contains(char::is_whitespace) {
+        true => format!("<{url}>"),
+        false => url.to_string(),
+    }
+}
+
+/// Slug of `heading` in the file `uri`, with the suffix of duplicate headings
+fn unique_slug(uri: &Url, heading: &Heading, state: &State) -> Option<String> {
+    let ast = state.ast_for_uri(uri)?;
+    heading_slugs(ast)
+        .into_iter()
+        .find(|(other, _)| std::ptr::eq(*other, heading))
+        .map(|(_, slug)| slug)
+}
+
+/// The text of `heading` for wikilinks, duplicate headings are linked by
+/// their unique slug
+fn wikilink_heading(uri: &Url, heading: &Heading, state: &State) -> Option<String> {
+    let unique_slug = unique_slug(uri, heading, state)?;
+    match unique_slug == heading_slug(heading) {
+        true => get_heading_text(heading),
+        false => Some(unique_slug),
+    }
+}
+
+/// Refactorings of the table at the start of the requested range: formatting
+/// it, inserting, deleting, moving and aligning the columns and rows of the
+/// cursor's cell. Each rewrites the whole table with consistent padding.
+fn table_actions(params: &CodeActionParams, state: &State) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    let buffer = md_file.buffer();
+    let offset = md_file.offset(&params.range.start);
+    let Some(node) = md_file.ast.find_table_for_position(offset) else {
+        return Vec::new();
+    };
+    let (Some(pos), Some((row, column))) =
+        (node.position.as_ref(), cell_at_offset(node, buffer, offset))
+    else {
+        return Vec::new();
+    };
+    let table = Table::new(node, buffer);
+    let edited = |edit: &dyn Fn(&mut Table)| {
+        let mut table = table.clone();
+        edit(&mut table);
+        table
+    };
+
+    let mut tables = vec![
+        ("Insert column left", edited(&|t| t.insert_column(column))),
+        (
+            "Insert column right",
+            edited(&|t| t.insert_column(column + 1)),
+        ),
+    ];
+    if column > 0 {
+        tables.push((
+            "Move column left",
+            edited(&|t| t.swap_columns(column - 1, column)),
+        ));
+    }
+    if column + 1 < table.column_count() {
+        tables.push((
+            "Move column right",
+            edited(&|t| t.swap_columns(column, column + 1)),
+        ));
+    }
+    if table.column_count() > 1 {
+        tables.push(("Delete column", edited(&|t| t.delete_column(column))));
+    }
+    match row {
+        // the header row can't be moved down or deleted
+        Some(row) if row > 0 => {
+            tables.push(("Insert row above", edited(&|t| t.insert_row(row))));
+            tables.push(("Insert row below", edited(&|t| t.insert_row(row + 1))));
+            tables.push(("Delete row", edited(&|t| t.delete_row(row))));
+        }
+        _ => tables.push(("Insert row below", edited(&|t| t.insert_row(1)))),
+    }
+    for (title, align) in [
+        ("Align column left", AlignKind::Left),
+        ("Align column center", AlignKind::Center),
+        ("Align column right", AlignKind::Right),
+        ("Remove column alignment", AlignKind::None),
+    ] {
+        if table.align[column] != align {
+            tables.push((title, edited(&|t| t.align_column(column, align))));
+        }
+    }
+
+    let range = md_file.range(pos);
+    let prefix = table_prefix(node, buffer);
+    let format_action = format_table(node, buffer).map(|new_text| {
+        code_action(
+            "Format table".to_string(),
+            CodeActionKind::REFACTOR_REWRITE,
+            req_uri,
+            vec![TextEdit { range, new_text }],
+        )
+    });
+    format_action
+        .into_iter()
+        .chain(tables.into_iter().map(|(title, table)| {
+            let text_edit = TextEdit {
+                range,
+                new_text: table.render(&prefix),
+            };
+            code_action(
+                title.to_string(),
+                CodeActionKind::REFACTOR_REWRITE,
+                req_uri,
+                vec![text_edit],
+            )
+        }))
+        .collect()
+}
+
+/// Quick fixes for broken links in the requested range: creating the missing
+/// file, linking to a similar heading or adding the heading and adding a stub
+/// for missing definitions
+fn broken_link_actions(params: &CodeActionParams, state: &State) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    let links = md_file.ast.find_links();
+    let mut code_actions = Vec::new();
+    for broken_link in check_links(&md_file.ast, req_uri, state) {
+        if !overlaps_requested_range(params, &broken_link.range) {
+            continue;
+        }
+        let link = links.iter().find(|link| {
+            link.position
+                .as_ref()
+                .is_some_and(|pos| md_file.range(pos) == broken_link.range)
+        });
+        let mut actions = match (&broken_link.kind, link) {
+            (BrokenLinkKind::FileNotFound, Some(link)) => create_file_action(link, req_uri, state)
+                .into_iter()
+                .collect(),
+            (BrokenLinkKind::HeadingNotFound, Some(link)) => {
+                heading_actions(link, md_file, req_uri, req_uri, state)
+            }
+            (BrokenLinkKind::ExternalHeadingNotFound, Some(link)) => {
+                match resolve_link(link, req_uri, state) {
+                    ResolvedLink::File { file_uri, .. } => {
+                        heading_actions(link, md_file, req_uri, file_uri, state)
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            (BrokenLinkKind::LinkRefNotFound | BrokenLinkKind::FootnoteRefNotFound, _) => {
+                stub_definition_action(md_file, &broken_link.range, req_uri)
+                    .into_iter()
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let diagnostics = context_diagnostics(params, &broken_link.range, broken_link.kind);
+        for code_action in &mut actions {
+            code_action.diagnostics = (!diagnostics.is_empty()).then(|| diagnostics.clone());
+        }
+        code_actions.extend(actions);
+    }
+    code_actions
+}
+
+/// Creates the markdown file `link` points to, with a title heading.
+/// Wikilinks create the file next to the linking file `req_uri`. Only offered
+/// if the client can create files.
+fn create_file_action(link: &Link, req_uri: &Url, state: &State) -> Option<CodeAction> {
+    if !state.create_files() {
+        return None;
+    }
+    let file = link.url.split('#').next().filter(|file| !file.is_empty())?;
+    let path = match MdLink::new(link) {
+        MdLink::WikiLink(_) => req_uri.to_file_path().ok()?.parent()?.join(file),
+        MdLink::NormalLink(_) => resolve_link_path(&url_decode(file), req_uri, state)?,
+    };
+    let path = match state.is_md_file(&path) {
+        true => path,
+        false => {
+            let extension = state.settings().file_extensions.first()?;
+            let mut path = path.into_os_string();
+            path.push(format!(".{extension}"));
+            PathBuf::from(path)
+        }
+    };
+    let uri = Url::from_file_path(&path).ok()?;
+    let title = path.file_stem()?.to_string_lossy();
+    let display_path = state
+        .workspace_root_for_uri(req_uri)
+        .and_then(|root| path_from_root(&root, &path))
+        .unwrap_or_else(|| path.to_string_lossy().into_owned());
+    let operations = vec![
+        DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+            uri: uri.clone(),
+            options: Some(CreateFileOptions {
+                overwrite: Some(false),
+                ignore_if_exists: Some(true),
+            }),
+            annotation_id: None,
+        })),
+        DocumentChangeOperation::Edit(TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+            edits: vec![OneOf::Left(TextEdit {
+                range: Range::default(),
+                new_text: format!("# {title}\n"),
+            })],
+        }),
+    ];
+    Some(CodeAction {
+        edit: Some(WorkspaceEdit {
+            changes: None,
+            document_changes: Some(DocumentChanges::Operations(operations)),
+            change_annotations: None,
+        }),
+        is_preferred: Some(true),
+        ..workspace_code_action(
+            format!("Create `{display_path}`"),
+            CodeActionKind::QUICKFIX,
+            HashMap::new(),
+        )
+    })
+}
+
+/// Most similar headings to a link to a missing heading
+const MAX_HEADING_SUGGESTIONS: usize = 3;
+
+/// Quick fixes for `link` in `md_file` to a missing heading in the file
+/// `target_uri`: linking to the most similar headings by edit distance or
+/// appending the heading to the file
+fn heading_actions(
+    link: &Link,
+    md_file: &MdFile,
+    req_uri: &Url,
+    target_uri: &Url,
+    state: &State,
+) -> Vec<CodeAction> {
+    let md_link = MdLink::new(link);
+    let (Some((start, end)), Some(index), Some(target_file)) = (
+        link_target_offsets(&md_link, md_file.buffer()),
+        link.url.find('#'),
+        state.md_files.get(target_uri),
+    ) else {
+        return Vec::new();
+    };
+    let fragment = url_decode(&link.url[index + 1..]);
+    let fragment_slug = slug(&fragment);
+    let fragment_range = md_file.range_from_offsets(start + index + 1, end);
+
+    let mut candidates: Vec<(usize, &Heading, String)> = heading_slugs(&target_file.ast)
+        .into_iter()
+        .map(|(heading, unique_slug)| {
+            (
+                edit_distance(&fragment_slug, &unique_slug),
+                heading,
+                unique_slug,
+            )
+        })
+        .filter(|(distance, _, unique_slug)| {
+            *distance
+                <= fragment_slug
+                    .chars()
+                    .count()
+                    .max(unique_slug.chars().count())
+                    / 2
+        })
+        .collect();
+    candidates.sort_by_key(|(distance, _, _)| *distance);
+
+    let mut code_actions: Vec<CodeAction> = candidates
+        .into_iter()
+        .take(MAX_HEADING_SUGGESTIONS)
+        .filter_map(|(_, heading, unique_slug)| {
+            let new_text = match md_link {
+                MdLink::WikiLink(_) => wikilink_heading(target_uri, heading, state)?,
+                MdLink::NormalLink(_) => unique_slug,
+            };
+            let text_edit = TextEdit {
+                range: fragment_range,
+                new_text,
+            };
+            Some(code_action(
+                format!("Link to heading `{}`", get_heading_text(heading)?),
+                CodeActionKind::QUICKFIX,
+                req_uri,
+                vec![text_edit],
+            ))
+        })
+        .collect();
+    if let Some(code_action) = code_actions.first_mut() {
+        code_action.is_preferred = Some(true);
+    }
+
+    // links contain the slug of the heading
+    let heading_text = match md_link {
+        MdLink::WikiLink(_) => fragment,
+        MdLink::NormalLink(_) => {
+            let text = fragment.replace('-', " ");
+            let mut chars = text.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => return code_actions,
+            }
+        }
+    };
+    let level = match target_file.ast.find_headings().is_empty() {
+        true => "#",
+        false => "##",
+    };
+    let text_edit = append_edit(target_file, &format!("{level} {heading_text}\n"), false);
+    code_actions.push(workspace_code_action(
+        format!("Add heading `{heading_text}`"),
+        CodeActionKind::QUICKFIX,
+        HashMap::from([(target_uri.clone(), vec![text_edit])]),
+    ));
+    code_actions
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Adds a stub definition for the broken link reference or footnote reference
+/// at `range`
+fn stub_definition_action(md_file: &MdFile, range: &Range, req_uri: &Url) -> Option<CodeAction> {
+    let start = md_file.offset(&range.start);
+    let end = md_file.offset(&range.end);
+    // `[text][label]` or `[^label]`
+    let source = md_file.buffer().get(start..end)?.strip_suffix(']')?;
+    let label = &source[source.rfind('[')? + 1..];
+    let (title, stub) = match label.starts_with('^') {
+        true => (
+            format!("Add footnote definition `{label}`"),
+            format!("[{label}]:\n"),
+        ),
+        false => (
+            format!("Add link definition `{label}`"),
+            format!("[{label}]: <>\n"),
+        ),
+    };
+    let mut code_action = code_action(
+        title,
+        CodeActionKind::QUICKFIX,
+        req_uri,
+        vec![append_edit(md_file, &stub, true)],
+    );
+    code_action.is_preferred = Some(true);
+    Some(code_action)
+}
+
+/// Edit deleting the whole lines of the definition at `pos`
+fn delete_definition_edit(md_file: &MdFile, pos: &AstPosition) -> TextEdit {
+    let buffer = md_file.buffer();
+    let line_start = buffer[..pos.start.offset].rfind('\n').map_or(0, |i| i + 1);
+    let start = if buffer[line_start..pos.start.offset].trim().is_empty() {
+        line_start
     } else {
-        code_actions.push(update_toc(&headings, &toc_tags, req_uri));
+        pos.start.offset
+    };
+    let end = match buffer[pos.end.offset..].strip_prefix('\n') {
+        Some(_) => pos.end.offset + 1,
+        None => pos.end.offset,
+    };
+    TextEdit {
+        range: md_file.range_from_offsets(start, end),
+        new_text: String::new(),
     }
+}
 
-    code_actions.into_iter().collect()
+/// Quick fixes for ambiguous wikilinks in the requested range, one for each
+/// file the link could point to
+fn disambiguate_link_actions(params: &CodeActionParams, state: &State) -> Vec<CodeAction> {
+    let req_uri = &params.text_document.uri;
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    let mut code_actions = Vec::new();
+    for link in md_file.ast.find_links() {
+        let ResolvedLink::Ambiguous { link, candidates } = resolve_link(link, req_uri, state)
+        else {
+            continue;
+        };
+        let Some(link_range) = link.position().map(|pos| md_file.range(pos)) else {
+            continue;
+        };
+        if !overlaps_requested_range(params, &link_range) {
+            continue;
+        }
+        let Some((start, end)) = link_target_offsets(&link, md_file.buffer()) else {
+            continue;
+        };
+        // keep the heading
+        let file_end = match link.url().find('#') {
+            Some(index) => start + index,
+            None => end,
+        };
+        let diagnostics = context_diagnostics(params, &link_range, BrokenLinkKind::AmbiguousLink);
+        for candidate in candidates {
+            let Some(new_target) = shortest_wikilink_target(candidate, req_uri, state) else {
+                continue;
+            };
+            let text_edit = TextEdit {
+                range: md_file.range_from_offsets(start, file_end),
+                new_text: new_target.clone(),
+            };
+            code_actions.push(quick_fix(
+                format!("Link to `{new_target}`"),
+                req_uri,
+                vec![text_edit],
+                diagnostics.clone(),
+            ));
+        }
+    }
+    code_actions
+}
+
+/// Whether the request's context asks for actions of `kind`, `only` can also
+/// contain a parent kind like `refactor`
+fn is_requested(params: &CodeActionParams, kind: &CodeActionKind) -> bool {
+    params.context.only.as_ref().is_none_or(|only| {
+        only.iter().any(|requested| {
+            kind.as_str()
+                .strip_prefix(requested.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+    })
+}
+
+fn overlaps_requested_range(params: &CodeActionParams, range: &Range) -> bool {
+    range.end >= params.range.start && range.start <= params.range.end
+}
+
+/// Diagnostics of the request's context of `kind` at `range`, these are fixed
+/// by a quick fix
+fn context_diagnostics(
+    params: &CodeActionParams,
+    range: &Range,
+    kind: BrokenLinkKind,
+) -> Vec<Diagnostic> {
+    let error_code = NumberOrString::Number(kind.error_code() as i32);
+    params
+        .context
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| {
+            &diagnostic.range == range && diagnostic.code.as_ref() == Some(&error_code)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Quick fix with `text_edits` in the file `req_uri`
+fn quick_fix(
+    title: String,
+    req_uri: &Url,
+    text_edits: Vec<TextEdit>,
+    diagnostics: Vec<Diagnostic>,
+) -> CodeAction {
+    CodeAction {
+        diagnostics: (!diagnostics.is_empty()).then_some(diagnostics),
+        ..code_action(title, CodeActionKind::QUICKFIX, req_uri, text_edits)
+    }
+}
+
+/// Code action of `kind` with `text_edits` in the file `req_uri`
+fn code_action(
+    title: String,
+    kind: CodeActionKind,
+    req_uri: &Url,
+    text_edits: Vec<TextEdit>,
+) -> CodeAction {
+    let changes = HashMap::from([(req_uri.clone(), text_edits)]);
+    workspace_code_action(title, kind, changes)
+}
+
+/// Code action of `kind` with edits in several files
+fn workspace_code_action(
+    title: String,
+    kind: CodeActionKind,
+    changes: HashMap<Url, Vec<TextEdit>>,
+) -> CodeAction {
+    CodeAction {
+        title,
+        kind: Some(kind),
+        diagnostics: None,
+        edit: Some(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        }),
+        command: None,
+        is_preferred: None,
+        disabled: None,
+        data: None,
+    }
 }
 
 fn toc(headings: &[&Heading]) -> String {
+    let mut slugger = Slugger::new();
     let toc = headings.iter().fold(String::new(), |mut acc, heading| {
-        match get_text_child(&heading.children) {
-            Some(text) => {
-                let link_text = text.value.clone();
-                let url_text = format!("#{}", text.value.to_lowercase().replace(' ', "-"));
-                let indent = (0..heading.depth - 1).fold(String::new(), |mut acc, _| {
-                    acc.push_str("  ");
-                    acc
-                });
-                let toc_entry = format!("{}- [{}]({})\n", indent, link_text, url_text);
-                acc.push_str(&toc_entry);
-                acc
-            }
-            _ => acc,
-        }
+        let link_text = inline_text(&heading.children);
+        let url_text = format!("#{}", slugger.slug(&link_text));
+        let indent = (0..heading.depth - 1).fold(String::new(), |mut acc, _| {
+            acc.push_str("  ");
+            acc
+        });
+        let toc_entry = format!("{}- [{}]({})\n", indent, link_text, url_text);
+        acc.push_str(&toc_entry);
+        acc
     });
     format!("{}\n{}{}\n\n", TOC_START, toc, TOC_END)
 }
@@ -127,3 +1263,347 @@ fn update_toc(headings: &[&Heading], toc_tags: &[&Html], req_uri: &Url) -> Optio
         data: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        CodeActionContext, PartialResultParams, TextDocumentIdentifier, WorkDoneProgressParams,
+    };
+
+    use super::*;
+
+    fn code_action_params(uri: &Url, range: Range) -> CodeActionParams {
+        CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range,
+            context: CodeActionContext::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }
+    }
+
+    #[test]
+    fn test_delete_unused_definition() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_buffer(
+            &uri,
+            "# Doc\n\n[a][used]\n\n[used]: /a\n[unused]: /b\n".to_string(),
+        );
+        let line = |line| Position { line, character: 0 };
+        let params = code_action_params(&uri, Range::new(line(5), line(5)));
+        let code_actions = delete_unused_definition_actions(&params, &state);
+        assert_eq!(code_actions.len(), 1);
+        assert_eq!(code_actions[0].title, "Delete unused definition `unused`");
+        let edits = &code_actions[0]
+            .edit
+            .as_ref()
+            .unwrap()
+            .changes
+            .as_ref()
+            .unwrap()[&uri];
+        assert_eq!(edits[0].range, Range::new(line(5), line(6)));
+        assert_eq!(edits[0].new_text, "");
+    }
+
+    #[test]
+    fn test_table_actions() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_buffer(
+            &uri,
+            "# Doc\n\n| a | b |\n|---|:-|\n| 1 | 2 |\n\nText\n".to_string(),
+        );
+        let params = code_action_params(&uri, Range::new(Position::new(4, 2), Position::new(4, 2)));
+        let code_actions = table_actions(&params, &state);
+        let new_text = |title: &str| {
+            let code_action = code_actions.iter().find(|a| a.title == title).unwrap();
+            let edits = &code_action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+            assert_eq!(
+                edits[0].range,
+                Range::new(Position::new(2, 0), Position::new(4, 9))
+            );
+            edits[0].new_text.clone()
+        };
+        assert_eq!(
+            new_text("Insert column right"),
+            "| a   |     | b   |\n| --- | --- | :-- |\n| 1   |     | 2   |"
+        );
+        assert_eq!(
+            new_text("Move column right"),
+            "| b   | a   |\n| :-- | --- |\n| 2   | 1   |"
+        );
+        assert_eq!(new_text("Delete row"), "| a   | b   |\n| --- | :-- |");
+        assert_eq!(
+            new_text("Align column right"),
+            "|   a | b   |\n| --: | :-- |\n|   1 | 2   |"
+        );
+        assert_eq!(
+            new_text("Format table"),
+            "| a   | b   |\n| --- | :-- |\n| 1   | 2   |"
+        );
+        assert!(code_actions.iter().all(|a| a.title != "Move column left"));
+        assert!(code_actions
+            .iter()
+            .all(|a| a.title != "Remove column alignment"));
+
+        let params = code_action_params(&uri, Range::new(Position::new(6, 0), Position::new(6, 0)));
+        assert!(table_actions(&params, &state).is_empty());
+    }
+
+    #[test]
+    fn test_convert_links() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(lsp_types::WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        let index_uri = Url::from_file_path(root.join("index.md")).unwrap();
+        let setup_uri = Url::from_file_path(root.join("notes/setup.md")).unwrap();
+        state.set_buffer(&setup_uri, "# Install\n\n[[index]]\n".to_string());
+        state.set_buffer(
+            &index_uri,
+            "# Index\n\n[[notes/setup#Install|setup guide]] [[#Index]] [[missing]]\n\n\
+             [Setup](notes/setup.md#install)\n\n| a |\n| - |\n| [x](notes/setup.md) |\n"
+                .to_string(),
+        );
+        let params = code_action_params(
+            &index_uri,
+            Range::new(Position::new(0, 0), Position::new(10, 0)),
+        );
+        let new_text = |code_action: &CodeAction, uri: &Url| -> Vec<String> {
+            code_action.edit.as_ref().unwrap().changes.as_ref().unwrap()[uri]
+                .iter()
+                .map(|edit| edit.new_text.clone())
+                .collect()
+        };
+        let converted: Vec<(String, Vec<String>)> = convert_link_actions(&params, &state)
+            .iter()
+            .map(|code_action| (code_action.title.clone(), new_text(code_action, &index_uri)))
+            .collect();
+        let to_markdown = "Convert to markdown link".to_string();
+        let to_wikilink = "Convert to wikilink".to_string();
+        assert_eq!(
+            converted,
+            vec![
+                (
+                    to_markdown.clone(),
+                    vec!["[setup guide](notes/setup.md#install)".to_string()]
+                ),
+                (to_markdown, vec!["[#Index](#index)".to_string()]),
+                (
+                    to_wikilink.clone(),
+                    vec!["[[setup#Install|Setup]]".to_string()]
+                ),
+                (to_wikilink, vec!["[[setup\\|x]]".to_string()]),
+            ]
+        );
+
+        let code_actions = convert_all_wikilinks_actions(&params, &state);
+        assert_eq!(code_actions.len(), 2);
+        assert_eq!(new_text(&code_actions[0], &index_uri).len(), 2);
+        assert_eq!(
+            new_text(&code_actions[1], &setup_uri),
+            vec!["[index](../index.md)".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_reference_links() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_buffer(
+            &uri,
+            "# Doc\n\nSee [the docs](https://example.com/docs \"Docs\"), [home](https://example.com) \
+             and [docs][].\n<https://example.com/auto>\n\n[home]: https://example.com\n\
+             [docs]: <https://example.com/a b>\n"
+                .to_string(),
+        );
+        let params =
+            code_action_params(&uri, Range::new(Position::new(2, 0), Position::new(2, 95)));
+        let new_texts = |code_action: &CodeAction| -> Vec<(Range, String)> {
+            code_action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri]
+                .iter()
+                .map(|edit| (edit.range, edit.new_text.clone()))
+                .collect()
+        };
+        let code_actions = reference_link_actions(&params, &state);
+        let titles: Vec<&str> = code_actions.iter().map(|a| a.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Convert to reference link",
+                "Convert to reference link",
+                "Convert to inline link"
+            ]
+        );
+        let end = Position::new(7, 0);
+        assert_eq!(
+            new_texts(&code_actions[0]),
+            vec![
+                (
+                    Range::new(Position::new(2, 4), Position::new(2, 47)),
+                    "[the docs][the-docs]".to_string()
+                ),
+                (
+                    Range::new(Position::new(6, 33), end),
+                    "\n[the-docs]: https://example.com/docs \"Docs\"\n".to_string()
+                ),
+            ]
+        );
+        // the existing definition is reused
+        assert_eq!(
+            new_texts(&code_actions[1]),
+            vec![(
+                Range::new(Position::new(2, 49), Position::new(2, 76)),
+                "[home][]".to_string()
+            )]
+        );
+        assert_eq!(
+            new_texts(&code_actions[2]),
+            vec![(
+                Range::new(Position::new(2, 81), Position::new(2, 89)),
+                "[docs](<https://example.com/a b>)".to_string()
+            )]
+        );
+
+        let code_actions = convert_all_reference_links_actions(&params, &state);
+        assert_eq!(code_actions.len(), 2);
+        // the autolink is kept
+        assert_eq!(new_texts(&code_actions[0]).len(), 3);
+        assert_eq!(
+            new_texts(&code_actions[1]),
+            vec![
+                (
+                    Range::new(Position::new(2, 81), Position::new(2, 89)),
+                    "[docs](<https://example.com/a b>)".to_string()
+                ),
+                (Range::new(Position::new(6, 0), end), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_requested_code_actions() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_code_action_resolve(true);
+        state.set_buffer(
+            &uri,
+            "# Doc\n\n[a](/a.md) [b][]\n\n[b]: /b.md\n".to_string(),
+        );
+        let mut params =
+            code_action_params(&uri, Range::new(Position::new(2, 0), Position::new(2, 0)));
+        params.context.only = Some(vec![CodeActionKind::REFACTOR]);
+        let refactorings = code_actions(&params, &state).unwrap();
+        assert_eq!(refactorings.len(), 1);
+        assert_eq!(refactorings[0].title, "Convert to reference link");
+
+        params.context.only = Some(vec![CodeActionKind::SOURCE]);
+        let source_actions = code_actions(&params, &state).unwrap();
+        let titles: Vec<&str> = source_actions.iter().map(|a| a.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Convert all links to reference links",
+                "Convert all reference links to inline links"
+            ]
+        );
+        // the edits are computed on resolve
+        assert!(source_actions.iter().all(|a| a.edit.is_none()));
+        let resolved = resolve_code_action(source_actions[1].clone(), &state);
+        let edits = &resolved.edit.unwrap().changes.unwrap()[&uri];
+        assert_eq!(edits[0].new_text, "[b](/b.md)");
+        assert_eq!(edits[1].new_text, "");
+    }
+
+    #[test]
+    fn test_broken_link_actions() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.set_create_files(true);
+        state.add_workspace_folder(lsp_types::WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        let index_uri = Url::from_file_path(root.join("index.md")).unwrap();
+        let setup_uri = Url::from_file_path(root.join("setup.md")).unwrap();
+        state.set_buffer(&setup_uri, "# Setup\n\n## Usages\n".to_string());
+        state.set_buffer(
+            &index_uri,
+            "# Index\n\n## Installation\n\n[a](#instalation) [b](setup.md#usage) [c](new%20page.md) \
+             [[other]]\n\n[x][missing] and [^note]\n"
+                .to_string(),
+        );
+        let params = code_action_params(
+            &index_uri,
+            Range::new(Position::new(4, 0), Position::new(6, 24)),
+        );
+        let code_actions = broken_link_actions(&params, &state);
+        let titles: Vec<&str> = code_actions.iter().map(|a| a.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Link to heading `Installation`",
+                "Add heading `Instalation`",
+                "Link to heading `Usages`",
+                "Add heading `Usage`",
+                "Create `/new page.md`",
+                "Create `/other.md`",
+                "Add link definition `missing`",
+                "Add footnote definition `^note`",
+            ]
+        );
+        let edits = |code_action: &CodeAction, uri: &Url| -> Vec<(Range, String)> {
+            code_action.edit.as_ref().unwrap().changes.as_ref().unwrap()[uri]
+                .iter()
+                .map(|edit| (edit.range, edit.new_text.clone()))
+                .collect()
+        };
+        assert_eq!(
+            edits(&code_actions[0], &index_uri),
+            vec![(
+                Range::new(Position::new(4, 5), Position::new(4, 16)),
+                "installation".to_string()
+            )]
+        );
+        assert_eq!(
+            edits(&code_actions[3], &setup_uri),
+            vec![(
+                Range::new(Position::new(2, 9), Position::new(3, 0)),
+                "\n\n## Usage\n".to_string()
+            )]
+        );
+        let Some(DocumentChanges::Operations(operations)) =
+            &code_actions[4].edit.as_ref().unwrap().document_changes
+        else {
+            panic!("expected resource operations");
+        };
+        let new_page_uri = Url::from_file_path(root.join("new page.md")).unwrap();
+        assert!(matches!(
+            &operations[0],
+            DocumentChangeOperation::Op(ResourceOp::Create(create)) if create.uri == new_page_uri
+        ));
+        assert!(matches!(
+            &operations[1],
+            DocumentChangeOperation::Edit(edit) if edit.edits == vec![OneOf::Left(TextEdit {
+                range: Range::default(),
+                new_text: "# new page\n".to_string(),
+            })]
+        ));
+        assert_eq!(
+            edits(&code_actions[7], &index_uri),
+            vec![(
+                Range::new(Position::new(6, 24), Position::new(7, 0)),
+                "\n\n[^note]:\n".to_string()
+            )]
+        );
+
+        // clients without `CreateFile` support don't get the action
+        state.set_create_files(false);
+        assert!(broken_link_actions(&params, &state)
+            .iter()
+            .all(|code_action| !code_action.title.starts_with("Create")));
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::settings::{CodeBlockSettings, FormatCommand};
+
+/// Time the external formatters of all code blocks in one formatting request
+/// get together, as they block the server
+pub const COMMANDS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Formatted `code` of a fenced code block with the language `tag`, `None`
+/// if there is no formatter for the language or formatting failed. External
+/// commands are run in `cwd` and killed at `deadline`.
+pub fn format_code_block(
+    tag: &str,
+    code: &str,
+    settings: &CodeBlockSettings,
+    cwd: Option<&Path>,
+    deadline: Instant,
+) -> Option<String> {
+    let tag = tag.to_lowercase();
+    let formatted = match settings.commands.get(&tag) {
+        Some(command) => run_command(command, code, cwd, deadline),
+        None if settings.builtin => match tag.as_str() {
+            "json" => format_json(code),
+            "toml" => format_toml(code),
+            "yaml" | "yml" => format_yaml(code),
+            _ => None,
+        },
+        None => None,
+    };
+    formatted.map(|formatted| formatted.trim_end().to_string())
+}
+
+/// Re-indents valid JSON token by token, so strings and numbers keep their
+/// source text (`1e3` and big integers aren't changed)
+fn format_json(code: &str) -> Option<String> {
+    serde_json::from_str::<serde::de::IgnoredAny>(code).ok()?;
+    let mut formatted = String::new();
+    let mut indent = 0;
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                formatted.push(c);
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    formatted.push(c);
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => break,
+                        _ => escaped = false,
+                    }
+                }
+            }
+            '{' | '[' => {
+                formatted.push(c);
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                let close = if c == '{' { '}' } else { ']' };
+                // empty objects and arrays stay on one line
+                match chars.next_if_eq(&close) {
+                    Some(close) => formatted.push(close),
+                    None => {
+                        indent += 1;
+                        push_line_break(&mut formatted, indent);
+                    }
+                }
+            }
+            '}' | ']' => {
+                indent -= 1;
+                push_line_break(&mut formatted, indent);
+                formatted.push(c);
+            }
+            ',' => {
+                formatted.push(c);
+                push_line_break(&mut formatted, indent);
+            }
+            ':' => formatted.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => formatted.push(c),
+        }
+    }
+    Some(formatted)
+}
+
+fn push_line_break(text: &mut String, indent: usize) {
+    text.push('\n');
+    text.push_str(&"  ".repeat(indent));
+}
+
+/// Comments would get lost, so TOML with comments is not formatted
+fn format_toml(code: &str) -> Option<String> {
+    if code.contains('#') {
+        return None;
+    }
+    let table: toml::Table = toml::from_str(code).ok()?;
+    let formatted = toml::to_string(&table).ok()?;
+    keeps_source_text(code, &formatted, toml_tokens).then_some(formatted)
+}
+
+/// Comments would get lost, so YAML with comments is not formatted. Neither
+/// is YAML where the source of a scalar changes, like expanded aliases,
+/// re-quoted strings or rewritten numbers.
+fn format_yaml(code: &str) -> Option<String> {
+    if code.contains('#') {
+        return None;
+    }
+    let value: serde_yaml::Value = serde_yaml::from_str(code).ok()?;
+    let formatted = serde_yaml::to_string(&value).ok()?;
+    keeps_source_text(code, &formatted, yaml_tokens).then_some(formatted)
+}
+
+/// Whether `formatted` only changes the whitespace between the keys and values
+/// of `code`, the serializers of TOML and YAML normalize numbers and strings
+fn keeps_source_text(code: &str, formatted: &str, tokens: fn(&str) -> Vec<&str>) -> bool {
+    tokens(code) == tokens(formatted)
+}
+
+/// Table headers, keys and values of the lines of TOML
+fn toml_tokens(toml: &str) -> Vec<&str> {
+    toml.lines()
+        .flat_map(|line| match line.split_once('=') {
+            Some((key, value)) => vec![key.trim(), value.trim()],
+            None => vec![line.trim()],
+        })
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Keys and values of the lines of YAML, without sequence markers
+fn yaml_tokens(yaml: &str) -> Vec<&str> {
+    yaml.lines()
+        .flat_map(|line| {
+            let mut line = line.trim();
+            while let Some(item) = line
+                .strip_prefix('-')
+                .filter(|item| item.is_empty() || item.starts_with(char::is_whitespace))
+            {
+                line = item.trim_start();
+            }
+            match line.split_once(": ") {
+                Some((key, value)) => vec![key, value.trim()],
+                None => vec![line.strip_suffix(':').unwrap_or(line)],
+            }
+        })
+        .filter(|token| !token.is_empty() && *token != "---")
+        .collect()
+}
+
+/// Output of `command` with `code` as input, `None` if it fails or doesn't
+/// finish before `deadline`
+fn run_command(
+    command: &FormatCommand,
+    code: &str,
+    cwd: Option<&Path>,
+    deadline: Instant,
+) -> Option<String> {
+    let args = command.args();
+    let (program, args) = args.split_first()?;
+    if Instant::now() >= deadline {
+        log::warn!("No time left to run `{program}`");
+        return None;
+    }
+    let mut process = Command::new(program);
+    process
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    if let Some(cwd) = cwd {
+        process.current_dir(cwd);
+    }
+    let mut child = process
+        .spawn()
+        .inspect_err(|e| log::warn!("Could not run `{program}`: {e}"))
+        .ok()?;
+
+    // write and read in threads, so a full pipe can't block
+    let mut stdin = child.stdin.take()?;
+    let input = code.to_string();
+    thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child.stdout.take()?;
+    let output = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let status = loop {
+        if let Some(status) = child.try_wait().ok()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            log::warn!("`{program}` timed out");
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        log::warn!("`{program}` failed: {status}");
+        return None;
+    }
+    output.join().ok()?.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn deadline() -> Instant {
+        Instant::now() + COMMANDS_TIMEOUT
+    }
+
+    #[test]
+    fn test_format_code_block_builtin() {
+        let settings = CodeBlockSettings::default();
+        assert_eq!(
+            format_code_block(
+                "json",
+                r#"{"b": 1, "a": [1,2]}"#,
+                &settings,
+                None,
+                deadline()
+            ),
+            Some("{\n  \"b\": 1,\n  \"a\": [\n    1,\n    2\n  ]\n}".to_string())
+        );
+        assert_eq!(
+            format_code_block(
+                "TOML",
+                "b=1\n[table]\na =   \"x\"",
+                &settings,
+                None,
+                deadline()
+            ),
+            Some("b = 1\n\n[table]\na = \"x\"".to_string())
+        );
+        assert_eq!(
+            format_code_block(
+                "yml",
+                "list:\n    - a\n    - b",
+                &settings,
+                None,
+                deadline()
+            ),
+            Some("list:\n- a\n- b".to_string())
+        );
+        // numbers and strings keep their source
+        assert_eq!(
+            format_code_block(
+                "json",
+                r#"{"n":1e3,"f":0.10,"big":123456789012345678901234567890,"s":"a\"}[","e":{ },"l":[]}"#,
+                &settings,
+                None,
+                deadline()
+            ),
+            Some(
+                "{\n  \"n\": 1e3,\n  \"f\": 0.10,\n  \"big\": 123456789012345678901234567890,\n  \
+                 \"s\": \"a\\\"}[\",\n  \"e\": {},\n  \"l\": []\n}"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            format_code_block("toml", "n = 1e3", &settings, None, deadline()),
+            None
+        );
+        assert_eq!(
+            format_code_block("yaml", "a: &x 1\nb: *x\n", &settings, None, deadline()),
+            None
+        );
+        assert_eq!(
+            format_code_block("yaml", "a: 'x'\nb: 0.10", &settings, None, deadline()),
+            None
+        );
+        // invalid or commented code is left alone
+        assert_eq!(
+            format_code_block("json", "{", &settings, None, deadline()),
+            None
+        );
+        assert_eq!(
+            format_code_block("yaml", "a: 1 # one", &settings, None, deadline()),
+            None
+        );
+        assert_eq!(
+            format_code_block("rust", "fn main(){}", &settings, None, deadline()),
+            None
+        );
+
+        let disabled = CodeBlockSettings {
+            builtin: false,
+            commands: HashMap::new(),
+        };
+        assert_eq!(
+            format_code_block("json", "{}", &disabled, None, deadline()),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_format_code_block_command() {
+        let settings = CodeBlockSettings {
+            builtin: true,
+            commands: HashMap::from([
+                (
+                    "shout".to_string(),
+                    FormatCommand::Line("tr a-z A-Z".to_string()),
+                ),
+                (
+                    "json".to_string(),
+                    FormatCommand::Args(vec!["cat".to_string()]),
+                ),
+                ("fail".to_string(), FormatCommand::Line("false".to_string())),
+            ]),
+        };
+        assert_eq!(
+            format_code_block("shout", "hello\n", &settings, None, deadline()),
+            Some("HELLO".to_string())
+        );
+        // commands take precedence over the builtin formatters
+        assert_eq!(
+            format_code_block("json", "{ }", &settings, None, deadline()),
+            Some("{ }".to_string())
+        );
+        assert_eq!(
+            format_code_block("fail", "code", &settings, None, deadline()),
+            None
+        );
+        // no command is started after the deadline
+        assert_eq!(
+            format_code_block("shout", "hello", &settings, None, Instant::now()),
+            None
+        );
+        let slow = CodeBlockSettings {
+            builtin: false,
+            commands: HashMap::from([(
+                "slow".to_string(),
+                FormatCommand::Line("sleep 5".to_string()),
+            )]),
+        };
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(100);
+        assert_eq!(format_code_block("slow", "", &slow, None, deadline), None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -1,13 +1,12 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
-use lsp_types::{
-    CompletionItem, CompletionItemKind, CompletionList, CompletionParams, Position, Range, Url,
-};
+use lsp_types::{CompletionItem, CompletionItemKind, CompletionList, CompletionParams, Url};
 use markdown::mdast::{FootnoteDefinition, Heading, Node, Text};
 
 use crate::{
     ast::{get_heading_text, TraverseNode},
     links::url_encode,
+    slug::heading_slugs,
     state::State,
 };
 
@@ -38,63 +37,70 @@ pub fn completion(params: CompletionParams, state: &State) -> Option<CompletionL
 }
 
 fn link_completion(req_uri: &Url, state: &State) -> Option<CompletionList> {
-    let root_uri = &state.workspace_folder()?.uri.to_file_path().ok()?;
-    let req_filepath = req_uri.to_file_path().ok()?;
-    let req_filename = req_filepath.file_name()?;
-    let completion_items: Option<Vec<CompletionItem>> = state
+    let root_uri = &state.workspace_root_for_uri(req_uri)?;
+    let completion_items: Vec<CompletionItem> = state
         .md_files
         .iter()
         .flat_map(|(url, md_file)| {
-            let ast = &md_file.ast;
-            let headings = ast.find_headings();
-            headings.into_iter().map(|heading| {
-                let file_path = url.to_file_path().ok()?;
-                let relative_path = relative_path(root_uri, &file_path)?;
-                let heading_text = get_heading_text(heading)?;
-                let range = link_detail_range(&md_file.ast, heading)?;
-                let detail = state.buffer_range_for_uri(url, &range)?;
-                let label = if Some(relative_path.as_str()) == req_filename.to_str() {
-                    format!("#{}", heading_text.to_lowercase().replace(' ', "-"))
-                } else {
-                    format!(
-                        "/{}#{}",
-                        url_encode(&relative_path),
-                        heading_text.to_lowercase().replace(' ', "-")
-                    )
-                };
+            let heading_slugs = heading_slugs(&md_file.ast);
+            heading_slugs
+                .into_iter()
+                .filter_map(move |(heading, slug)| {
+                    let file_path = url.to_file_path().ok()?;
+                    let relative_path = relative_path(root_uri, &file_path)?;
+                    let detail = link_detail(url, &md_file.ast, heading, state)?;
+                    let label = if url == req_uri {
+                        format!("#{}", slug)
+                    } else {
+                        format!("/{}#{}", url_encode(&relative_path), slug)
+                    };
 
-                Some(CompletionItem {
-                    label,
-                    kind: Some(CompletionItemKind::TEXT),
-                    detail: Some(detail),
-                    ..CompletionItem::default()
+                    Some(CompletionItem {
+                        label,
+                        kind: Some(CompletionItemKind::TEXT),
+                        detail: Some(detail),
+                        ..CompletionItem::default()
+                    })
                 })
-            })
         })
+        .chain(asset_completion(root_uri, state))
         .collect();
-    completion_items.map(|comp_items| CompletionList {
+    Some(CompletionList {
         is_incomplete: false,
-        items: comp_items,
+        items: completion_items,
+    })
+}
+
+/// Paths of non-markdown files, e.g. for images
+fn asset_completion<'a>(
+    root_uri: &'a Path,
+    state: &'a State,
+) -> impl Iterator<Item = CompletionItem> + 'a {
+    state.asset_files().filter_map(move |url| {
+        let file_path = url.to_file_path().ok()?;
+        let relative_path = relative_path(root_uri, &file_path)?;
+        Some(CompletionItem {
+            label: format!("/{}", url_encode(&relative_path)),
+            kind: Some(CompletionItemKind::FILE),
+            ..CompletionItem::default()
+        })
     })
 }
 
 fn wikilink_completion(req_uri: &Url, state: &State) -> Option<CompletionList> {
-    let root_uri = PathBuf::from(&state.workspace_folder()?.uri.path());
-    let req_filepath = req_uri.to_file_path().ok()?;
-    let req_filename = req_filepath.file_name()?;
-    let completion_items: Option<Vec<CompletionItem>> = state
+    let root_uri = &state.workspace_root_for_uri(req_uri)?;
+    let completion_items: Vec<CompletionItem> = state
         .md_files
         .iter()
         .flat_map(|(url, md_file)| {
             let ast = &md_file.ast;
             let headings = ast.find_headings();
-            headings.into_iter().map(|heading| {
+            headings.into_iter().filter_map(move |heading| {
                 let file_path = url.to_file_path().ok()?;
-                let relative_path = relative_path(&root_uri, &file_path)?;
+                let relative_path = relative_path(root_uri, &file_path)?;
                 let heading_text = get_heading_text(heading)?;
-                let range = link_detail_range(&md_file.ast, heading)?;
-                let detail = state.buffer_range_for_uri(url, &range)?;
-                let label = if Some(relative_path.as_str()) == req_filename.to_str() {
+                let detail = link_detail(url, &md_file.ast, heading, state)?;
+                let label = if url == req_uri {
                     format!("#{}", heading_text)
                 } else {
                     let path = relative_path.split_once('.')?.0;
@@ -110,36 +116,22 @@ fn wikilink_completion(req_uri: &Url, state: &State) -> Option<CompletionList> {
             })
         })
         .collect();
-    completion_items.map(|comp_items| CompletionList {
+    Some(CompletionList {
         is_incomplete: false,
-        items: comp_items,
+        items: completion_items,
     })
 }
 
-fn link_detail_range(ast: &Node, heading: &Heading) -> Option<Range> {
+/// Content of the section of `heading`
+fn link_detail(uri: &Url, ast: &Node, heading: &Heading, state: &State) -> Option<String> {
     let heading_pos = heading.position.as_ref()?;
-    match ast.find_next_heading(heading_pos.end.line, heading.depth) {
-        Some(next_heading) => Some(Range {
-            start: Position {
-                line: (heading_pos.start.line - 1) as u32,
-                character: (heading_pos.start.column - 1) as u32,
-            },
-            end: Position {
-                line: next_heading.position.as_ref()?.start.line as u32,
-                character: next_heading.position.as_ref()?.end.line as u32,
-            },
-        }),
-        None => Some(Range {
-            start: Position {
-                line: (heading_pos.start.line - 1) as u32,
-                character: (heading_pos.start.column - 1) as u32,
-            },
-            end: Position {
-                line: 999999,
-                character: 999999,
-            },
-        }),
-    }
+    let end = match ast.find_next_heading(heading_pos.end.line, heading.depth) {
+        Some(next_heading) => next_heading.position.as_ref()?.start.offset,
+        None => state.buffer_for_uri(uri)?.len(),
+    };
+    state
+        .buffer_slice_for_uri(uri, heading_pos.start.offset, end)
+        .map(ToString::to_string)
 }
 
 fn link_ref_completion(req_uri: &Url, state: &State) -> Option<CompletionList> {
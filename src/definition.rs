@@ -1,10 +1,7 @@
 use lsp_types::{
     GotoDefinitionParams, GotoDefinitionResponse, Location, Position as LspPosition, Range, Url,
 };
-use markdown::{
-    mdast::{FootnoteReference, Link, LinkReference, Node},
-    unist,
-};
+use markdown::mdast::{FootnoteReference, Link, LinkReference, Node};
 
 use crate::{
     ast::TraverseNode,
@@ -15,12 +12,12 @@ use crate::{
 pub fn definition(params: &GotoDefinitionParams, state: &State) -> Option<GotoDefinitionResponse> {
     let position_params = &params.text_document_position_params;
     let req_uri = &position_params.text_document.uri;
-    let LspPosition { line, character } = position_params.position;
     let req_ast = state.ast_for_uri(req_uri).unwrap();
-    let node = req_ast.find_linkable_for_position(line, character);
+    let offset = state.offset_for_uri(req_uri, &position_params.position)?;
+    let node = req_ast.find_linkable_for_position(offset);
 
     let location = match node? {
-        Node::Link(link) => handle_link_to_heading(link, state),
+        Node::Link(link) => handle_link_to_heading(req_uri, link, state),
         Node::LinkReference(link_ref) => handle_link_ref(req_uri, link_ref, state),
         Node::FootnoteReference(foot_ref) => handle_link_footnote(req_uri, foot_ref, state),
         _ => None,
@@ -28,8 +25,8 @@ pub fn definition(params: &GotoDefinitionParams, state: &State) -> Option<GotoDe
     location.map(GotoDefinitionResponse::Scalar)
 }
 
-fn handle_link_to_heading(link: &Link, state: &State) -> Option<Location> {
-    match resolve_link(link, state) {
+fn handle_link_to_heading(req_uri: &Url, link: &Link, state: &State) -> Option<Location> {
+    match resolve_link(link, req_uri, state) {
         ResolvedLink::File { file_uri, .. } => Some(Location {
             uri: file_uri.clone(),
             range: range_zero(),
@@ -41,7 +38,7 @@ fn handle_link_to_heading(link: &Link, state: &State) -> Option<Location> {
             file_uri, heading, ..
         } => Some(Location {
             uri: file_uri.clone(),
-            range: range_from_position(heading.position.as_ref()?),
+            range: state.range_for_uri(file_uri, heading.position.as_ref()?)?,
         }),
         _ => None,
     }
@@ -52,9 +49,11 @@ fn handle_link_ref(req_uri: &Url, link_ref: &LinkReference, state: &State) -> Op
     req_ast
         .find_definition_for_identifier(&link_ref.identifier)
         .and_then(|def| {
-            def.position.as_ref().map(|pos| Location {
-                uri: req_uri.clone(),
-                range: range_from_position(pos),
+            def.position.as_ref().and_then(|pos| {
+                Some(Location {
+                    uri: req_uri.clone(),
+                    range: state.range_for_uri(req_uri, pos)?,
+                })
             })
         })
 }
@@ -68,28 +67,16 @@ fn handle_link_footnote(
     req_ast
         .find_foot_definition_for_identifier(&foot_ref.identifier)
         .and_then(|foot_def| {
-            foot_def.position.as_ref().map(|pos| Location {
-                uri: req_uri.clone(),
-                range: range_from_position(pos),
+            foot_def.position.as_ref().and_then(|pos| {
+                Some(Location {
+                    uri: req_uri.clone(),
+                    range: state.range_for_uri(req_uri, pos)?,
+                })
             })
         })
 }
 
-/// Takes markdown::unist::Position`, returned `Position in `Range` from `lsp_types::Position`
-pub fn range_from_position(position: &unist::Position) -> Range {
-    Range {
-        start: LspPosition {
-            line: (position.start.line - 1) as u32,
-            character: (position.start.column - 1) as u32,
-        },
-        end: LspPosition {
-            line: (position.end.line - 1) as u32,
-            character: (position.end.column - 1) as u32,
-        },
-    }
-}
-
-fn range_zero() -> Range {
+pub fn range_zero() -> Range {
     Range {
         start: LspPosition {
             line: 0,
@@ -1,15 +1,32 @@
-use std::hash::Hash;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+};
 
 use itertools::Itertools;
-use lsp_types::{Position, Range, Url};
-use markdown::mdast::{Link, Node};
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag,
+    DocumentDiagnosticParams, DocumentDiagnosticReport, DocumentDiagnosticReportKind,
+    DocumentDiagnosticReportResult, FullDocumentDiagnosticReport, Location, NumberOrString, Range,
+    RelatedFullDocumentDiagnosticReport, RelatedUnchangedDocumentDiagnosticReport,
+    UnchangedDocumentDiagnosticReport, Url, WorkspaceDiagnosticParams, WorkspaceDiagnosticReport,
+    WorkspaceDiagnosticReportResult, WorkspaceDocumentDiagnosticReport,
+    WorkspaceFullDocumentDiagnosticReport, WorkspaceUnchangedDocumentDiagnosticReport,
+};
+use markdown::{
+    mdast::{FootnoteReference, Image, ImageReference, Link, LinkReference, Node},
+    unist::Position as AstPosition,
+};
 use regex::Regex;
 
 use crate::{
     ast::TraverseNode,
-    definition::range_from_position,
-    links::{resolve_link, ResolvedLink},
-    state::State,
+    definition::range_zero,
+    links::{is_external_url, resolve_asset, resolve_link, ResolvedLink},
+    slug::heading_slug,
+    state::{path_from_root, MdFile, State},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -26,6 +43,20 @@ pub enum BrokenLinkKind {
     LinkRefNotFound,
     /// Footnote reference to non-existent footnote definition
     FootnoteRefNotFound,
+    /// Wikilink matching several files
+    AmbiguousLink,
+    /// Link or image to non-existent non-markdown file
+    AssetNotFound,
+    /// Definition without LinkReference
+    UnusedDefinition,
+    /// FootnoteDefinition without FootnoteReference
+    UnusedFootnoteDefinition,
+    /// Definition with the same identifier as a previous one
+    DuplicateDefinition,
+    /// FootnoteDefinition with the same identifier as a previous one
+    DuplicateFootnoteDefinition,
+    /// Heading with the same slug as a previous one
+    DuplicateHeading,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -33,6 +64,7 @@ pub struct BrokenLink {
     pub kind: BrokenLinkKind,
     pub range: Range,
     pub message: String,
+    pub related_information: Vec<DiagnosticRelatedInformation>,
 }
 
 impl Hash for BrokenLink {
@@ -45,17 +77,269 @@ impl Hash for BrokenLink {
     }
 }
 
-impl BrokenLink {
+impl BrokenLinkKind {
+    /// Name of the rule, used in settings and suppression comments
+    pub fn name(&self) -> &'static str {
+        match self {
+            BrokenLinkKind::IvalidSyntax => "InvalidSyntax",
+            BrokenLinkKind::HeadingNotFound => "HeadingNotFound",
+            BrokenLinkKind::ExternalHeadingNotFound => "ExternalHeadingNotFound",
+            BrokenLinkKind::FileNotFound => "FileNotFound",
+            BrokenLinkKind::LinkRefNotFound => "LinkRefNotFound",
+            BrokenLinkKind::FootnoteRefNotFound => "FootnoteRefNotFound",
+            BrokenLinkKind::AmbiguousLink => "AmbiguousLink",
+            BrokenLinkKind::AssetNotFound => "AssetNotFound",
+            BrokenLinkKind::UnusedDefinition => "UnusedDefinition",
+            BrokenLinkKind::UnusedFootnoteDefinition => "UnusedFootnoteDefinition",
+            BrokenLinkKind::DuplicateDefinition => "DuplicateDefinition",
+            BrokenLinkKind::DuplicateFootnoteDefinition => "DuplicateFootnoteDefinition",
+            BrokenLinkKind::DuplicateHeading => "DuplicateHeading",
+        }
+    }
+
     pub fn error_code(&self) -> usize {
-        match self.kind {
+        match self {
             BrokenLinkKind::IvalidSyntax => 0,
             BrokenLinkKind::HeadingNotFound => 1,
             BrokenLinkKind::ExternalHeadingNotFound => 2,
             BrokenLinkKind::FileNotFound => 3,
             BrokenLinkKind::LinkRefNotFound => 4,
             BrokenLinkKind::FootnoteRefNotFound => 5,
+            BrokenLinkKind::AmbiguousLink => 6,
+            BrokenLinkKind::AssetNotFound => 7,
+            BrokenLinkKind::UnusedDefinition => 8,
+            BrokenLinkKind::UnusedFootnoteDefinition => 9,
+            BrokenLinkKind::DuplicateDefinition => 10,
+            BrokenLinkKind::DuplicateFootnoteDefinition => 11,
+            BrokenLinkKind::DuplicateHeading => 12,
+        }
+    }
+
+    /// Severity if not configured otherwise
+    pub fn default_severity(&self) -> DiagnosticSeverity {
+        match self {
+            BrokenLinkKind::UnusedDefinition | BrokenLinkKind::UnusedFootnoteDefinition => {
+                DiagnosticSeverity::INFORMATION
+            }
+            BrokenLinkKind::DuplicateDefinition
+            | BrokenLinkKind::DuplicateFootnoteDefinition
+            | BrokenLinkKind::DuplicateHeading => DiagnosticSeverity::WARNING,
+            _ => DiagnosticSeverity::ERROR,
+        }
+    }
+
+    pub fn tags(&self) -> Option<Vec<DiagnosticTag>> {
+        match self {
+            BrokenLinkKind::UnusedDefinition | BrokenLinkKind::UnusedFootnoteDefinition => {
+                Some(vec![DiagnosticTag::UNNECESSARY])
+            }
+            _ => None,
+        }
+    }
+}
+
+impl BrokenLink {
+    pub fn error_code(&self) -> usize {
+        self.kind.error_code()
+    }
+}
+
+/// All diagnostics for the file `req_uri`, with the severities from the
+/// settings and without the suppressed ones
+pub fn diagnostics(req_uri: &Url, state: &State) -> Vec<Diagnostic> {
+    let Some(ast) = state.ast_for_uri(req_uri) else {
+        return Vec::new();
+    };
+    let suppressions = suppressions(ast);
+    // for link, reflink, footnote check if their definitions exist
+    check_links(ast, req_uri, state)
+        .into_iter()
+        .chain(check_unused_definitions(req_uri, state))
+        .chain(check_duplicates(req_uri, state))
+        .filter(|it| !is_suppressed(&suppressions, it))
+        .filter_map(|it| {
+            let severity = match state.settings().rule_severity(it.kind.name()) {
+                Some(rule_severity) => rule_severity.diagnostic_severity()?,
+                None => it.kind.default_severity(),
+            };
+            Some((it, severity))
+        })
+        .map(|(it, severity)| Diagnostic {
+            range: it.range,
+            severity: Some(severity),
+            code: Some(NumberOrString::Number(
+                it.error_code()
+                    .try_into()
+                    .expect("error code value to large for i32"),
+            )),
+            code_description: None,
+            source: Some("md-lsp".to_string()),
+            message: it.message,
+            related_information: (!it.related_information.is_empty())
+                .then_some(it.related_information),
+            tags: it.kind.tags(),
+            data: None,
+        })
+        .collect()
+}
+
+const DISABLE_NEXT_LINE: &str = "md-lsp-disable-next-line";
+
+/// Rules disabled by `<!-- md-lsp-disable-next-line Rule1 Rule2 -->` per
+/// (0-based) line, an empty list disables all rules
+fn suppressions(ast: &Node) -> HashMap<u32, Vec<&str>> {
+    ast.find_html_nodes()
+        .into_iter()
+        .filter_map(|html| {
+            let comment = html
+                .value
+                .trim()
+                .strip_prefix("<!--")?
+                .strip_suffix("-->")?
+                .trim();
+            let rules = comment.strip_prefix(DISABLE_NEXT_LINE)?;
+            if !rules.is_empty() && !rules.starts_with(char::is_whitespace) {
+                return None;
+            }
+            let rules = rules
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|rule| !rule.is_empty())
+                .collect();
+            // the unist line of the comment's end is the 0-based next line
+            let next_line = html.position.as_ref()?.end.line as u32;
+            Some((next_line, rules))
+        })
+        .collect()
+}
+
+fn is_suppressed(suppressions: &HashMap<u32, Vec<&str>>, broken_link: &BrokenLink) -> bool {
+    suppressions
+        .get(&broken_link.range.start.line)
+        .is_some_and(|rules| rules.is_empty() || rules.contains(&broken_link.kind.name()))
+}
+
+/// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_diagnostic
+pub fn document_diagnostic(
+    params: &DocumentDiagnosticParams,
+    state: &State,
+) -> DocumentDiagnosticReportResult {
+    let report = match document_diagnostic_report(
+        &params.text_document.uri,
+        params.previous_result_id.as_deref(),
+        state,
+    ) {
+        DocumentDiagnosticReportKind::Full(full_document_diagnostic_report) => {
+            DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report,
+            })
+        }
+        DocumentDiagnosticReportKind::Unchanged(unchanged_document_diagnostic_report) => {
+            DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                related_documents: None,
+                unchanged_document_diagnostic_report,
+            })
         }
+    };
+    DocumentDiagnosticReportResult::Report(report)
+}
+
+/// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_diagnostic
+pub fn workspace_diagnostic(
+    params: &WorkspaceDiagnosticParams,
+    state: &State,
+) -> WorkspaceDiagnosticReportResult {
+    let previous_result_ids: HashMap<&Url, &str> = params
+        .previous_result_ids
+        .iter()
+        .map(|previous| (&previous.uri, previous.value.as_str()))
+        .collect();
+    let items = state
+        .md_files
+        .keys()
+        .map(|uri| {
+            let previous_result_id = previous_result_ids.get(uri).copied();
+            match document_diagnostic_report(uri, previous_result_id, state) {
+                DocumentDiagnosticReportKind::Full(full_document_diagnostic_report) => {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri: uri.clone(),
+                        version: None,
+                        full_document_diagnostic_report,
+                    })
+                }
+                DocumentDiagnosticReportKind::Unchanged(unchanged_document_diagnostic_report) => {
+                    WorkspaceDocumentDiagnosticReport::Unchanged(
+                        WorkspaceUnchangedDocumentDiagnosticReport {
+                            uri: uri.clone(),
+                            version: None,
+                            unchanged_document_diagnostic_report,
+                        },
+                    )
+                }
+            }
+        })
+        .collect();
+    WorkspaceDiagnosticReportResult::Report(WorkspaceDiagnosticReport { items })
+}
+
+/// Full report of the diagnostics of `uri`, or `Unchanged` if they are the same
+/// as the ones with `previous_result_id`
+fn document_diagnostic_report(
+    uri: &Url,
+    previous_result_id: Option<&str>,
+    state: &State,
+) -> DocumentDiagnosticReportKind {
+    let cache = state.diagnostics_cache();
+    if !cache.0.borrow().contains_key(uri) {
+        let items = diagnostics(uri, state);
+        let result_id = result_id(&items);
+        cache
+            .0
+            .borrow_mut()
+            .insert(uri.clone(), CachedReport { result_id, items });
+    }
+    let reports = cache.0.borrow();
+    let report = &reports[uri];
+    let result_id = report.result_id.clone();
+    if previous_result_id == Some(result_id.as_str()) {
+        DocumentDiagnosticReportKind::Unchanged(UnchangedDocumentDiagnosticReport { result_id })
+    } else {
+        DocumentDiagnosticReportKind::Full(FullDocumentDiagnosticReport {
+            result_id: Some(result_id),
+            items: report.items.clone(),
+        })
+    }
+}
+
+/// Pulled diagnostics per file, so unchanged files are not checked again for
+/// every `workspace/diagnostic`. `State` drops the entries of changed files.
+#[derive(Debug, Default)]
+pub struct DiagnosticsCache(RefCell<HashMap<Url, CachedReport>>);
+
+#[derive(Debug)]
+struct CachedReport {
+    result_id: String,
+    items: Vec<Diagnostic>,
+}
+
+impl DiagnosticsCache {
+    pub fn remove(&self, uri: &Url) {
+        self.0.borrow_mut().remove(uri);
     }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+/// Identifies a set of diagnostics, diagnostics of a file can also change when
+/// other files change, so the id is derived from the diagnostics themselves
+fn result_id(diagnostics: &[Diagnostic]) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(diagnostics)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    format!("{:x}", hasher.finish())
 }
 
 pub fn check_links(ast: &Node, req_uri: &Url, state: &State) -> Vec<BrokenLink> {
@@ -69,7 +353,8 @@ pub fn check_links(ast: &Node, req_uri: &Url, state: &State) -> Vec<BrokenLink>
                         v.push(broken_link)
                     }
                 }
-                Node::Link(link) => v.extend(handle_broken_link(state, link)),
+                Node::Link(link) => v.extend(handle_broken_link(link, req_uri, state)),
+                Node::Image(image) => v.extend(handle_broken_image(image, req_uri, state)),
                 Node::Text(t) => {
                     // Link that gets not parsed because not valid
                     if t.value.contains("](") {
@@ -93,51 +378,121 @@ pub fn check_links(ast: &Node, req_uri: &Url, state: &State) -> Vec<BrokenLink>
     v.into_iter().unique().collect()
 }
 
-fn handle_broken_link(state: &State, link: &Link) -> Vec<BrokenLink> {
-    let resolved_link = resolve_link(link, state);
+fn handle_broken_link(link: &Link, req_uri: &Url, state: &State) -> Vec<BrokenLink> {
+    let resolved_link = resolve_link(link, req_uri, state);
     let mut broken_links = Vec::new();
+    let Some(range) = link
+        .position
+        .as_ref()
+        .and_then(|pos| state.range_for_uri(req_uri, pos))
+    else {
+        return broken_links;
+    };
     match resolved_link {
         ResolvedLink::File { file_uri, .. } => {
-            if let Some(pos) = &link.position {
-                let file_path = file_uri.to_file_path().unwrap();
-                let file_name = file_path.file_name().and_then(|f| f.to_str());
-                if link.url.contains('#') {
-                    if let Some(f) = file_name {
-                        broken_links.push(BrokenLink {
-                            kind: BrokenLinkKind::ExternalHeadingNotFound,
-                            range: range_from_position(pos),
-                            message: format!(
-                                "Link to non-existent heading `{}` in file `{}`",
-                                link.url, f
-                            ),
-                        })
-                    }
+            let file_path = file_uri.to_file_path().unwrap();
+            let file_name = file_path.file_name().and_then(|f| f.to_str());
+            if link.url.contains('#') {
+                if let Some(f) = file_name {
+                    broken_links.push(BrokenLink {
+                        kind: BrokenLinkKind::ExternalHeadingNotFound,
+                        range,
+                        message: format!(
+                            "Link to non-existent heading `{}` in file `{}`",
+                            link.url, f
+                        ),
+                        related_information: Vec::new(),
+                    })
                 }
-            };
+            }
+        }
+        ResolvedLink::Unresolved if is_asset_link(&link.url, state) => {
+            broken_links.push(BrokenLink {
+                kind: BrokenLinkKind::AssetNotFound,
+                range,
+                message: format!("Link to non-existent file `{}`", link.url),
+                related_information: Vec::new(),
+            });
         }
         ResolvedLink::Unresolved => {
-            if let Some(pos) = &link.position {
-                broken_links.push(BrokenLink {
-                    kind: BrokenLinkKind::FileNotFound,
-                    range: range_from_position(pos),
-                    message: format!("Link to non-existent file `{}`", link.url),
+            broken_links.push(BrokenLink {
+                kind: BrokenLinkKind::FileNotFound,
+                range,
+                message: format!("Link to non-existent file `{}`", link.url),
+                related_information: Vec::new(),
+            });
+        }
+        ResolvedLink::Ambiguous { candidates, .. } => {
+            let root = state.workspace_root_for_uri(req_uri);
+            let candidate_paths = candidates
+                .iter()
+                .filter_map(|uri| {
+                    let path = uri.to_file_path().ok()?;
+                    match &root {
+                        Some(root) => path_from_root(root, &path),
+                        None => Some(path.to_string_lossy().into_owned()),
+                    }
                 })
-            };
+                .map(|path| format!("`{path}`"))
+                .join(", ");
+            broken_links.push(BrokenLink {
+                kind: BrokenLinkKind::AmbiguousLink,
+                range,
+                message: format!("Ambiguous link `{}`, matches {}", link.url, candidate_paths),
+                related_information: candidates
+                    .into_iter()
+                    .map(|uri| DiagnosticRelatedInformation {
+                        location: Location {
+                            uri: uri.clone(),
+                            range: range_zero(),
+                        },
+                        message: "Candidate for the link".to_string(),
+                    })
+                    .collect(),
+            });
         }
         _ => {}
     };
     broken_links
 }
 
+/// Whether `url` points to a file with an extension, that is not a markdown
+/// file extension
+fn is_asset_link(url: &str, state: &State) -> bool {
+    let file = Path::new(url.split('#').next().unwrap_or_default());
+    file.extension().is_some() && !state.is_md_file(file)
+}
+
+fn handle_broken_image(image: &Image, req_uri: &Url, state: &State) -> Option<BrokenLink> {
+    if image.url.is_empty()
+        || is_external_url(&image.url)
+        || resolve_asset(&image.url, req_uri, state).is_some()
+    {
+        return None;
+    }
+    let range = state.range_for_uri(req_uri, image.position.as_ref()?)?;
+    Some(BrokenLink {
+        kind: BrokenLinkKind::AssetNotFound,
+        range,
+        message: format!("Image of non-existent file `{}`", image.url),
+        related_information: Vec::new(),
+    })
+}
+
 fn handle_broken_heading_link(link: &Link, req_uri: &Url, state: &State) -> Option<BrokenLink> {
     let found = state
         .ast_for_uri(req_uri)
         .and_then(|ast| ast.find_heading_for_link(link));
-    if let (Some(pos), None) = (&link.position, found) {
+    let range = link
+        .position
+        .as_ref()
+        .and_then(|pos| state.range_for_uri(req_uri, pos));
+    if let (Some(range), None) = (range, found) {
         Some(BrokenLink {
             kind: BrokenLinkKind::HeadingNotFound,
-            range: range_from_position(pos),
+            range,
             message: format!("Link to non-existent heading `{}`", &link.url),
+            related_information: Vec::new(),
         })
     } else {
         None
@@ -145,10 +500,10 @@ fn handle_broken_heading_link(link: &Link, req_uri: &Url, state: &State) -> Opti
 }
 
 fn handle_broken_ref(req_uri: &Url, state: &State) -> Vec<BrokenLink> {
-    let ast = state.ast_for_uri(req_uri).unwrap();
+    let md_file = state.md_files.get(req_uri).unwrap();
     let mut ranges = Vec::new();
     let re = Regex::new(r"\[([^]]+)\]\[([^]]+)\]").unwrap();
-    find_broken_link_ref(ast, &re, &mut ranges);
+    find_broken_link_ref(&md_file.ast, md_file, &re, &mut ranges);
     ranges
         .iter()
         .map(|broken_link_ref| BrokenLink {
@@ -158,15 +513,16 @@ fn handle_broken_ref(req_uri: &Url, state: &State) -> Vec<BrokenLink> {
                 "Link reference to non-existent link definition `{}`",
                 broken_link_ref.text
             ),
+            related_information: Vec::new(),
         })
         .collect::<Vec<_>>()
 }
 
 fn handle_broken_footnote_ref(req_uri: &Url, state: &State) -> Vec<BrokenLink> {
-    let ast = state.ast_for_uri(req_uri).unwrap();
+    let md_file = state.md_files.get(req_uri).unwrap();
     let mut ranges = Vec::new();
     let re = Regex::new(r"\[(\^\S+)\]").unwrap();
-    find_broken_link_ref(ast, &re, &mut ranges);
+    find_broken_link_ref(&md_file.ast, md_file, &re, &mut ranges);
     ranges
         .iter()
         .map(|broken_link_ref| BrokenLink {
@@ -176,21 +532,165 @@ fn handle_broken_footnote_ref(req_uri: &Url, state: &State) -> Vec<BrokenLink> {
                 "Footnote reference to non-existent footnote definition `{}`",
                 broken_link_ref.text
             ),
+            related_information: Vec::new(),
         })
         .collect()
 }
 
 fn handle_invalid_link(req_uri: &Url, state: &State) -> Vec<BrokenLink> {
-    let ast = state.ast_for_uri(req_uri).unwrap();
+    let md_file = state.md_files.get(req_uri).unwrap();
     let mut ranges = Vec::new();
     let re = Regex::new(r"\[[^\]]+]\(([^)]+)\)").unwrap();
-    find_broken_link_ref(ast, &re, &mut ranges);
+    find_broken_link_ref(&md_file.ast, md_file, &re, &mut ranges);
     ranges
         .iter()
         .map(|broken_link_ref| BrokenLink {
             kind: BrokenLinkKind::IvalidSyntax,
             range: broken_link_ref.range,
             message: format!("Invalid Link `{}`", broken_link_ref.text),
+            related_information: Vec::new(),
+        })
+        .collect()
+}
+
+fn check_unused_definitions(req_uri: &Url, state: &State) -> Vec<BrokenLink> {
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    unused_definitions(&md_file.ast)
+        .into_iter()
+        .filter_map(|node| {
+            let (kind, message) = match node {
+                Node::Definition(def) => (
+                    BrokenLinkKind::UnusedDefinition,
+                    format!(
+                        "Unused link definition `{}`",
+                        def.label.as_deref().unwrap_or(&def.identifier)
+                    ),
+                ),
+                Node::FootnoteDefinition(def) => (
+                    BrokenLinkKind::UnusedFootnoteDefinition,
+                    format!(
+                        "Unused footnote definition `{}`",
+                        def.label.as_deref().unwrap_or(&def.identifier)
+                    ),
+                ),
+                _ => return None,
+            };
+            Some(BrokenLink {
+                kind,
+                range: md_file.range(node.position()?),
+                message,
+                related_information: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Definitions, footnote definitions and heading anchors occurring more than
+/// once. All but the first occurrence are reported.
+fn check_duplicates(req_uri: &Url, state: &State) -> Vec<BrokenLink> {
+    let Some(md_file) = state.md_files.get(req_uri) else {
+        return Vec::new();
+    };
+    let mut definitions = Vec::new();
+    let mut footnote_definitions = Vec::new();
+    let mut headings = Vec::new();
+    for node in md_file.ast.ast_iter() {
+        let Some(pos) = node.position() else {
+            continue;
+        };
+        match node {
+            Node::Definition(def) => definitions.push((def.identifier.clone(), pos)),
+            Node::FootnoteDefinition(def) => {
+                footnote_definitions.push((def.identifier.clone(), pos))
+            }
+            Node::Heading(heading) => headings.push((heading_slug(heading), pos)),
+            _ => {}
+        }
+    }
+    let duplicate_kinds = [
+        (
+            BrokenLinkKind::DuplicateDefinition,
+            definitions,
+            "Duplicate link definition",
+            "Other definition of",
+        ),
+        (
+            BrokenLinkKind::DuplicateFootnoteDefinition,
+            footnote_definitions,
+            "Duplicate footnote definition",
+            "Other definition of",
+        ),
+        (
+            BrokenLinkKind::DuplicateHeading,
+            headings,
+            "Duplicate heading anchor",
+            "Other heading with anchor",
+        ),
+    ];
+    let mut broken_links = Vec::new();
+    for (kind, occurrences, message, related_message) in duplicate_kinds {
+        for (i, (key, pos)) in occurrences.iter().enumerate() {
+            let others: Vec<&AstPosition> = occurrences
+                .iter()
+                .enumerate()
+                .filter(|(j, (other_key, _))| *j != i && other_key == key)
+                .map(|(_, (_, other_pos))| *other_pos)
+                .collect();
+            let is_first = occurrences[..i]
+                .iter()
+                .all(|(other_key, _)| other_key != key);
+            if others.is_empty() || is_first {
+                continue;
+            }
+            let key = match kind {
+                BrokenLinkKind::DuplicateHeading => format!("#{key}"),
+                _ => key.clone(),
+            };
+            broken_links.push(BrokenLink {
+                kind: kind.clone(),
+                range: md_file.range(pos),
+                message: format!("{message} `{key}`"),
+                related_information: others
+                    .into_iter()
+                    .map(|other_pos| DiagnosticRelatedInformation {
+                        location: Location {
+                            uri: req_uri.clone(),
+                            range: md_file.range(other_pos),
+                        },
+                        message: format!("{related_message} `{key}`"),
+                    })
+                    .collect(),
+            });
+        }
+    }
+    broken_links
+}
+
+/// `Definition`s and `FootnoteDefinition`s without any reference to them
+pub fn unused_definitions(ast: &Node) -> Vec<&Node> {
+    let mut used_definitions = HashSet::new();
+    let mut used_footnote_definitions = HashSet::new();
+    for node in ast.ast_iter() {
+        match node {
+            Node::LinkReference(LinkReference { identifier, .. })
+            | Node::ImageReference(ImageReference { identifier, .. }) => {
+                used_definitions.insert(identifier.as_str());
+            }
+            Node::FootnoteReference(FootnoteReference { identifier, .. }) => {
+                used_footnote_definitions.insert(identifier.as_str());
+            }
+            _ => {}
+        }
+    }
+    ast.ast_iter()
+        .filter(|node| match node {
+            Node::Definition(def) => !used_definitions.contains(def.identifier.as_str()),
+            Node::FootnoteDefinition(def) => {
+                !used_footnote_definitions.contains(def.identifier.as_str())
+            }
+            _ => false,
         })
         .collect()
 }
@@ -200,38 +700,222 @@ struct BrokenLinkRef<'a> {
     text: &'a str,
 }
 
-fn find_broken_link_ref<'a>(node: &'a Node, re: &Regex, positions: &mut Vec<BrokenLinkRef<'a>>) {
+fn find_broken_link_ref<'a>(
+    node: &'a Node,
+    md_file: &'a MdFile,
+    re: &Regex,
+    positions: &mut Vec<BrokenLinkRef<'a>>,
+) {
     if let Some(children) = node.children() {
         for child in children {
-            find_broken_link_ref(child, re, positions)
+            find_broken_link_ref(child, md_file, re, positions)
         }
     }
 
     if let Node::Text(text) = node {
-        for (i, line) in text.value.lines().enumerate() {
-            for captures in re.captures_iter(line) {
-                let start = captures.get(0).map(|m| m.start());
-                let end = captures.get(0).map(|m| m.end());
-                let broken_link_text = captures.get(0).map(|m| m.as_str());
-
-                if let (Some(s), Some(e), Some(text), Some(pos)) =
-                    (start, end, broken_link_text, &text.position)
-                {
-                    positions.push(BrokenLinkRef {
-                        range: Range {
-                            start: Position {
-                                line: (pos.start.line + i - 1) as u32,
-                                character: (pos.start.column + s - 1) as u32,
-                            },
-                            end: Position {
-                                line: (pos.start.line + i - 1) as u32,
-                                character: (pos.start.column + e - 1) as u32,
-                            },
-                        },
-                        text,
-                    });
-                }
+        let Some(pos) = &text.position else {
+            return;
+        };
+        let Some(source) = md_file.buffer().get(pos.start.offset..pos.end.offset) else {
+            return;
+        };
+        let mut line_offset = pos.start.offset;
+        for line in source.split('\n') {
+            for m in re.find_iter(line) {
+                positions.push(BrokenLinkRef {
+                    range: md_file
+                        .range_from_offsets(line_offset + m.start(), line_offset + m.end()),
+                    text: m.as_str(),
+                });
             }
+            line_offset += line.len() + 1;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::WorkspaceFolder;
+
+    use super::*;
+
+    #[test]
+    fn test_check_asset_links() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        state.load_file(&Url::from_file_path(root.join("Cargo.toml")).unwrap());
+        let uri = Url::from_file_path(root.join("doc.md")).unwrap();
+        state.set_buffer(
+            &uri,
+            "[config](Cargo.toml) [config](./missing.toml)\n\n\
+            ![arch](/img/arch.png) ![remote](https://example.com/a.png) ![toml](Cargo.toml)\n"
+                .to_string(),
+        );
+        let broken_links = check_links(state.ast_for_uri(&uri).unwrap(), &uri, &state);
+        let broken: Vec<(BrokenLinkKind, &str)> = broken_links
+            .iter()
+            .map(|broken_link| (broken_link.kind.clone(), broken_link.message.as_str()))
+            .collect();
+        assert_eq!(
+            broken,
+            vec![
+                (
+                    BrokenLinkKind::AssetNotFound,
+                    "Link to non-existent file `./missing.toml`"
+                ),
+                (
+                    BrokenLinkKind::AssetNotFound,
+                    "Image of non-existent file `/img/arch.png`"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unused_definitions() {
+        let md = "[used][a] ![img][b] [^1]\n\n[a]: /a\n[b]: /b.png\n[c]: /c\n\n\
+            [^1]: used\n[^2]: unused\n";
+        let ast = markdown::to_mdast(md, &markdown::ParseOptions::gfm()).unwrap();
+        let unused: Vec<&str> = unused_definitions(&ast)
+            .into_iter()
+            .filter_map(|node| node.position())
+            .map(|pos| &md[pos.start.offset..pos.end.offset])
+            .collect();
+        assert_eq!(unused, vec!["[c]: /c", "[^2]: unused"]);
+    }
+
+    #[test]
+    fn test_check_duplicates() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_buffer(
+            &uri,
+            "# Setup\n\n## **Setup**\n\n[foo]: /a\n[Foo]: /b\n\n[^1]: a\n\n[^1]: b\n".to_string(),
+        );
+        let duplicates = check_duplicates(&uri, &state);
+        let found: Vec<(&str, u32, Vec<u32>)> = duplicates
+            .iter()
+            .map(|duplicate| {
+                (
+                    duplicate.message.as_str(),
+                    duplicate.range.start.line,
+                    duplicate
+                        .related_information
+                        .iter()
+                        .map(|related| related.location.range.start.line)
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("Duplicate link definition `foo`", 5, vec![4]),
+                ("Duplicate footnote definition `1`", 9, vec![7]),
+                ("Duplicate heading anchor `#setup`", 2, vec![0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_document_diagnostic_result_id() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_buffer(&uri, "[a](#missing)\n".to_string());
+        let mut params = DocumentDiagnosticParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri.clone() },
+            identifier: None,
+            previous_result_id: None,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(report)) =
+            document_diagnostic(&params, &state)
+        else {
+            panic!("expected full report");
+        };
+        let full_report = report.full_document_diagnostic_report;
+        assert_eq!(full_report.items.len(), 1);
+
+        params.previous_result_id = full_report.result_id;
+        assert!(matches!(
+            document_diagnostic(&params, &state),
+            DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(_))
+        ));
+
+        state.set_buffer(&uri, "# Missing\n\n[a](#missing)\n".to_string());
+        assert!(matches!(
+            document_diagnostic(&params, &state),
+            DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(_))
+        ));
+    }
+
+    #[test]
+    fn test_diagnostics_cache() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        let uri = |name: &str| Url::from_file_path(root.join(name)).unwrap();
+        state.set_buffer(&uri("a.md"), "[b](b.md#setup)\n".to_string());
+        state.set_buffer(&uri("b.md"), "# Install\n".to_string());
+        state.set_buffer(&uri("c.md"), "# C\n".to_string());
+        let cached = |state: &State, name: &str| {
+            state
+                .diagnostics_cache()
+                .0
+                .borrow()
+                .get(&uri(name))
+                .map(|report| report.items.len())
+        };
+        let params = WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: Vec::new(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        workspace_diagnostic(&params, &state);
+        assert_eq!(cached(&state, "a.md"), Some(1));
+
+        // unrelated files keep the cached diagnostics of `a.md`
+        state.set_buffer(&uri("c.md"), "# Changed\n".to_string());
+        assert_eq!(cached(&state, "a.md"), Some(1));
+        assert_eq!(cached(&state, "c.md"), None);
+
+        // changes of linked files drop them
+        state.set_buffer(&uri("b.md"), "# Setup\n".to_string());
+        assert_eq!(cached(&state, "a.md"), None);
+        workspace_diagnostic(&params, &state);
+        assert_eq!(cached(&state, "a.md"), Some(0));
+    }
+
+    #[test]
+    fn test_diagnostics_settings_and_suppressions() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_client_settings(serde_json::json!({
+            "diagnostics": { "HeadingNotFound": "warning", "UnusedDefinition": "off" }
+        }));
+        state.set_buffer(
+            &uri,
+            "[a](#missing)\n\n\
+            <!-- md-lsp-disable-next-line HeadingNotFound -->\n[b](#missing)\n\n\
+            <!-- md-lsp-disable-next-line FileNotFound -->\n[c](#missing)\n\n\
+            [unused]: /x\n"
+                .to_string(),
+        );
+        let found: Vec<(u32, Option<DiagnosticSeverity>)> = diagnostics(&uri, &state)
+            .iter()
+            .map(|diagnostic| (diagnostic.range.start.line, diagnostic.severity))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, Some(DiagnosticSeverity::WARNING)),
+                (6, Some(DiagnosticSeverity::WARNING)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use dprint_core::configuration::GlobalConfiguration;
+use dprint_plugin_markdown::configuration::{Configuration, ConfigurationBuilder};
+use lsp_types::Url;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    settings::{FormattingSettings, NewLineKind, TextWrap},
+    state::State,
+};
+
+const DPRINT_CONFIG_FILES: [&str; 4] = [
+    "dprint.json",
+    ".dprint.json",
+    "dprint.jsonc",
+    ".dprint.jsonc",
+];
+
+const EDITORCONFIG_FILE: &str = ".editorconfig";
+
+/// Markdown part of a `dprint.json`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DprintConfig {
+    #[serde(flatten)]
+    global: FormattingSettings,
+    markdown: FormattingSettings,
+}
+
+/// Configuration of the formatter for the file `req_uri`. Options are taken
+/// from the settings, then from `dprint.json` and `.editorconfig` at the root
+/// of the workspace folder.
+pub fn markdown_config(req_uri: &Url, state: &State) -> Configuration {
+    let root = state.workspace_root_for_uri(req_uri);
+    let dprint_config = root.as_deref().and_then(read_dprint_config);
+    // dprint maintains line breaks by default
+    let default_text_wrap = match dprint_config {
+        Some(_) => TextWrap::Maintain,
+        None => TextWrap::Always,
+    };
+    let editorconfig = root
+        .as_deref()
+        .zip(req_uri.to_file_path().ok())
+        .map(|(root, path)| read_editorconfig(root, &path))
+        .unwrap_or_default();
+    let settings = state
+        .settings()
+        .formatting
+        .clone()
+        .or(dprint_config.unwrap_or_default())
+        .or(editorconfig);
+    build_config(settings, default_text_wrap)
+}
+
+fn build_config(settings: FormattingSettings, default_text_wrap: TextWrap) -> Configuration {
+    let global_config = GlobalConfiguration {
+        line_width: Some(settings.line_width.unwrap_or(80)),
+        // not used for markdown
+        use_tabs: None,
+        indent_width: None,
+        new_line_kind: Some(settings.new_line_kind.unwrap_or(NewLineKind::Auto).into()),
+    };
+    let mut builder = ConfigurationBuilder::new();
+    builder
+        .global_config(global_config)
+        .text_wrap(settings.text_wrap.unwrap_or(default_text_wrap).into());
+    if let Some(emphasis_kind) = settings.emphasis_kind {
+        builder.emphasis_kind(emphasis_kind.into());
+    }
+    if let Some(strong_kind) = settings.strong_kind {
+        builder.strong_kind(strong_kind.into());
+    }
+    if let Some(directive) = &settings.ignore_directive {
+        builder.ignore_directive(directive);
+    }
+    if let Some(directive) = &settings.ignore_file_directive {
+        builder.ignore_file_directive(directive);
+    }
+    if let Some(directive) = &settings.ignore_start_directive {
+        builder.ignore_start_directive(directive);
+    }
+    if let Some(directive) = &settings.ignore_end_directive {
+        builder.ignore_end_directive(directive);
+    }
+    builder.build()
+}
+
+/// Global options of `dprint.json` in `root`, overridden by its `markdown`
+/// section
+fn read_dprint_config(root: &Path) -> Option<FormattingSettings> {
+    let path: PathBuf = DPRINT_CONFIG_FILES
+        .iter()
+        .map(|file_name| root.join(file_name))
+        .find(|path| path.is_file())?;
+    let content = fs::read_to_string(&path).ok()?;
+    let reader = json_comments::StripComments::new(content.as_bytes());
+    match serde_json::from_reader::<_, DprintConfig>(reader) {
+        Ok(config) => Some(config.markdown.or(config.global)),
+        Err(e) => {
+            log::warn!("Invalid {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Options of `.editorconfig` in `root` for the file `path`, later sections
+/// override earlier ones
+fn read_editorconfig(root: &Path, path: &Path) -> FormattingSettings {
+    let mut settings = FormattingSettings::default();
+    let Ok(content) = fs::read_to_string(root.join(EDITORCONFIG_FILE)) else {
+        return settings;
+    };
+    let relative_path = path.strip_prefix(root).unwrap_or(path);
+    let mut section_matches = false;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section_matches = editorconfig_glob_matches(glob, relative_path);
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if !section_matches {
+            continue;
+        }
+        let value = value.trim().to_lowercase();
+        match key.trim().to_lowercase().as_str() {
+            "max_line_length" => settings.line_width = value.parse().ok().or(settings.line_width),
+            "end_of_line" => match value.as_str() {
+                "lf" => settings.new_line_kind = Some(NewLineKind::Lf),
+                "crlf" => settings.new_line_kind = Some(NewLineKind::Crlf),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    settings
+}
+
+/// Whether the section `glob` of an `.editorconfig` applies to `path`
+/// (relative to the `.editorconfig`). Globs without `/` match the file name
+/// in any directory.
+fn editorconfig_glob_matches(glob: &str, path: &Path) -> bool {
+    let mut pattern = String::from("^");
+    let mut braces = 0;
+    let mut chars = glob.trim_start_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '{' => {
+                braces += 1;
+                pattern.push_str("(?:");
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                pattern.push(')');
+            }
+            ',' if braces > 0 => pattern.push('|'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    let Ok(regex) = Regex::new(&pattern) else {
+        return false;
+    };
+    if glob.contains('/') {
+        let path: Vec<String> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        regex.is_match(&path.join("/"))
+    } else {
+        path.file_name()
+            .is_some_and(|name| regex.is_match(&name.to_string_lossy()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dprint_plugin_markdown::configuration::{StrongKind, TextWrap as DprintTextWrap};
+    use lsp_types::WorkspaceFolder;
+
+    use super::*;
+
+    #[test]
+    fn test_editorconfig_glob_matches() {
+        let path = Path::new("docs/guide/intro.md");
+        assert!(editorconfig_glob_matches("*", path));
+        assert!(editorconfig_glob_matches("*.md", path));
+        assert!(editorconfig_glob_matches("*.{md,markdown}", path));
+        assert!(editorconfig_glob_matches("docs/**.md", path));
+        assert!(editorconfig_glob_matches("/docs/**", path));
+        assert!(!editorconfig_glob_matches("docs/*.md", path));
+        assert!(!editorconfig_glob_matches("*.rs", path));
+    }
+
+    #[test]
+    fn test_markdown_config() {
+        let root =
+            std::env::temp_dir().join(format!("md-lsp-format-config-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join(".editorconfig"),
+            "root = true\n\n[*]\nmax_line_length = 100\nend_of_line = crlf\n\n[*.md]\nend_of_line = lf\nindent_size = 2\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("dprint.json"),
+            "{\n  // shared with CI\n  \"lineWidth\": 120,\n  \"markdown\": { \"strongKind\": \"underscores\" }\n}\n",
+        )
+        .unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        state.set_client_settings(serde_json::json!({ "formatting": { "lineWidth": 90 } }));
+        let uri = Url::from_file_path(root.join("doc.md")).unwrap();
+        let config = markdown_config(&uri, &state);
+        let editorconfig = read_editorconfig(&root, &root.join("doc.md"));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(config.line_width, 90);
+        assert!(config.strong_kind == StrongKind::Underscores);
+        assert!(config.text_wrap == DprintTextWrap::Maintain);
+        assert_eq!(
+            editorconfig,
+            FormattingSettings {
+                line_width: Some(100),
+                new_line_kind: Some(NewLineKind::Lf),
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -1,28 +1,36 @@
+use std::{path::Path, time::Instant};
+
 use anyhow::Result;
+use dprint_plugin_markdown::configuration::Configuration;
 use lsp_types::{
-    DocumentFormattingParams, DocumentRangeFormattingParams, Position, Range, TextEdit,
+    DocumentFormattingParams, DocumentOnTypeFormattingParams, DocumentRangeFormattingParams,
+    Position, TextEdit,
+};
+use similar::{
+    algorithms::{myers, Capture, Replace},
+    DiffOp, TextDiff,
 };
 
-use crate::state::State;
+use crate::{
+    ast::TraverseNode,
+    code_blocks::{format_code_block, COMMANDS_TIMEOUT},
+    format_config::markdown_config,
+    settings::CodeBlockSettings,
+    state::{MdFile, State},
+    table::format_table,
+};
 
 pub fn formatting(params: &DocumentFormattingParams, state: &State) -> Option<Vec<TextEdit>> {
     let req_uri = &params.text_document.uri;
+    let md_file = state.md_files.get(req_uri)?;
+    let config = markdown_config(req_uri, state);
+    let root = state.workspace_root_for_uri(req_uri);
+    let code_blocks = &state.settings().code_blocks;
 
-    state.buffer_for_uri(req_uri).map(|doc| {
-        let lines = doc.lines().count();
-        let last_char = doc.lines().last().map(|it| it.chars().count());
-        let formatted = format_md(doc);
-
-        if let (Some(last_char), Ok(Some(form))) = (last_char, formatted) {
-            let range = range_for_full_doc(lines as u32, last_char as u32);
-            Some(vec![TextEdit {
-                range,
-                new_text: form,
-            }])
-        } else {
-            None
-        }
-    })?
+    match format_md(md_file.buffer(), &config, code_blocks, root.as_deref()) {
+        Ok(Some(formatted)) => Some(text_edits(md_file, 0, md_file.buffer(), &formatted)),
+        _ => None,
+    }
 }
 
 pub fn range_formatting(
@@ -30,60 +38,293 @@ pub fn range_formatting(
     state: &State,
 ) -> Option<Vec<TextEdit>> {
     let req_uri = &params.text_document.uri;
-    let req_range = &params.range;
+    let md_file = state.md_files.get(req_uri)?;
+    let req_start = md_file.offset(&params.range.start);
+    let req_end = md_file.offset(&params.range.end).max(req_start);
+    let (start, end) = block_offsets(md_file, req_start, req_end);
+    let text = md_file.buffer().get(start..end)?;
+    log::info!("TEXT TO FORMAT: {:?}", text);
+    let config = markdown_config(req_uri, state);
+    let root = state.workspace_root_for_uri(req_uri);
+    let code_blocks = &state.settings().code_blocks;
 
-    state.buffer_range_for_uri(req_uri, req_range).map(|text| {
-        log::info!("TEXT TO FORMAT: {:?}", text);
-        let formatted = format_md(&text);
-        if let Ok(Some(form)) = formatted {
-            let range = *req_range;
-            Some(vec![TextEdit {
-                range,
-                new_text: form,
-            }])
-        } else {
-            None
-        }
-    })?
+    // edits are line-wise, so they are kept if they are on the requested lines
+    let first_line = Position::new(params.range.start.line, 0);
+    let after_last_line = Position::new(params.range.end.line + 1, 0);
+    match format_md(text, &config, code_blocks, root.as_deref()) {
+        Ok(Some(formatted)) => Some(
+            text_edits(md_file, start, text, &formatted)
+                .into_iter()
+                .filter(|edit| edit.range.start >= first_line && edit.range.end <= after_last_line)
+                .collect(),
+        ),
+        _ => None,
+    }
 }
 
-fn format_md(md: &str) -> Result<Option<String>> {
-    let global_config = dprint_core::configuration::GlobalConfiguration {
-        line_width: Some(80),
-        use_tabs: Some(true),
-        indent_width: Some(4),
-        new_line_kind: Some(dprint_core::configuration::NewLineKind::Auto),
-    };
-    let config = dprint_plugin_markdown::configuration::ConfigurationBuilder::new()
-        .global_config(global_config)
-        .text_wrap(dprint_plugin_markdown::configuration::TextWrap::Always)
-        .build();
-    dprint_plugin_markdown::format_text(md, &config, |_, _, _| Ok(None))
+/// Realigns the table at the cursor after typing `|` or Tab, independent of the
+/// markdown formatter
+pub fn on_type_formatting(
+    params: &DocumentOnTypeFormattingParams,
+    state: &State,
+) -> Option<Vec<TextEdit>> {
+    // can be switched off after initialization
+    if !state.settings().format_tables_on_type {
+        return None;
+    }
+    let req_uri = &params.text_document_position.text_document.uri;
+    let md_file = state.md_files.get(req_uri)?;
+    let offset = md_file.offset(&params.text_document_position.position);
+    let table = md_file.ast.find_table_for_position(offset)?;
+    let pos = table.position.as_ref()?;
+    // the typed tab is only used to trigger formatting, it is dropped by
+    // replacing it with a space of the same length
+    let mut buffer = md_file.buffer().to_string();
+    if params.ch == "\t" && buffer[..offset].ends_with('\t') {
+        buffer.replace_range(offset - 1..offset, " ");
+    }
+    let new_text = format_table(table, &buffer)?;
+    Some(vec![TextEdit {
+        range: md_file.range(pos),
+        new_text,
+    }])
 }
 
-fn range_for_full_doc(num_lines: u32, last_char: u32) -> Range {
-    Range {
-        start: Position {
-            line: 0,
-            character: 0,
-        },
-        end: Position {
-            line: num_lines,
-            character: last_char,
-        },
+/// Byte offsets of the whole lines of the top-level blocks overlapping
+/// `start..end`. Lists, tables and block quotes are formatted as a whole, so
+/// nested blocks keep the indentation of their containers.
+fn block_offsets(md_file: &MdFile, start: usize, end: usize) -> (usize, usize) {
+    let (start, end) = md_file
+        .ast
+        .children()
+        .into_iter()
+        .flatten()
+        .filter_map(|node| node.position())
+        .filter(|pos| pos.start.offset <= end && pos.end.offset >= start)
+        .fold((start, end), |(start, end), pos| {
+            (start.min(pos.start.offset), end.max(pos.end.offset))
+        });
+    let buffer = md_file.buffer();
+    let line_start = buffer[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = buffer[end..]
+        .find('\n')
+        .map_or(buffer.len(), |i| end + i + 1);
+    (line_start, line_end)
+}
+
+/// Formats `md` and its fenced code blocks, external code formatters are run
+/// in `cwd` and share `COMMANDS_TIMEOUT`
+fn format_md(
+    md: &str,
+    config: &Configuration,
+    code_blocks: &CodeBlockSettings,
+    cwd: Option<&Path>,
+) -> Result<Option<String>> {
+    let deadline = Instant::now() + COMMANDS_TIMEOUT;
+    dprint_plugin_markdown::format_text(md, config, |tag, code, _| {
+        Ok(format_code_block(tag, code, code_blocks, cwd, deadline))
+    })
+}
+
+/// Edits turning `original`, starting at byte offset `start` of `md_file`,
+/// into `formatted`. Unchanged lines are not touched and changed lines are
+/// only edited where words differ, so the editor keeps cursor position, folds
+/// and marks.
+fn text_edits(md_file: &MdFile, start: usize, original: &str, formatted: &str) -> Vec<TextEdit> {
+    let diff = TextDiff::from_lines(original, formatted);
+    let line_offsets = slice_offsets(diff.old_slices(), start);
+    let new_lines = diff.new_slices();
+    diff_ops(diff.old_slices(), new_lines)
+        .iter()
+        .filter(|op| !matches!(op, DiffOp::Equal { .. }))
+        .flat_map(|op| {
+            let (old_start, old_end) = (
+                line_offsets[op.old_range().start],
+                line_offsets[op.old_range().end],
+            );
+            let old_text = &original[old_start - start..old_end - start];
+            let new_text = new_lines[op.new_range()].concat();
+            word_edits(md_file, old_start, old_text, &new_text)
+        })
+        .collect()
+}
+
+/// Word-wise edits turning `original`, starting at byte offset `start` of
+/// `md_file`, into `formatted`
+fn word_edits(md_file: &MdFile, start: usize, original: &str, formatted: &str) -> Vec<TextEdit> {
+    let diff = TextDiff::from_words(original, formatted);
+    let word_offsets = slice_offsets(diff.old_slices(), start);
+    let new_words = diff.new_slices();
+    diff_ops(diff.old_slices(), new_words)
+        .iter()
+        .filter(|op| !matches!(op, DiffOp::Equal { .. }))
+        .map(|op| TextEdit {
+            range: md_file.range_from_offsets(
+                word_offsets[op.old_range().start],
+                word_offsets[op.old_range().end],
+            ),
+            new_text: new_words[op.new_range()].concat(),
+        })
+        .collect()
+}
+
+/// Diff of the slices `old` and `new`, adjacent deletions and insertions are
+/// combined to replacements. `TextDiff::ops` is not used, as its compaction
+/// can reorder the ops.
+fn diff_ops(old: &[&str], new: &[&str]) -> Vec<DiffOp> {
+    let mut replace = Replace::new(Capture::new());
+    let Ok(()) = myers::diff(&mut replace, old, 0..old.len(), new, 0..new.len());
+    replace.into_inner().into_ops()
+}
+
+/// Byte offsets of the start of each slice and of the end of the last one
+fn slice_offsets(slices: &[&str], start: usize) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(slices.len() + 1);
+    offsets.push(start);
+    for slice in slices {
+        offsets.push(offsets[offsets.len() - 1] + slice.len());
     }
+    offsets
 }
 
 #[cfg(test)]
 mod tests {
+    use lsp_types::{
+        FormattingOptions, Position, Range, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+    };
+
     use super::*;
 
+    /// Applies `edits` to `text`, from the last to the first
+    fn apply_edits(md_file: &MdFile, edits: &[TextEdit]) -> String {
+        let mut text = md_file.buffer().to_string();
+        for edit in edits.iter().rev() {
+            let start = md_file.offset(&edit.range.start);
+            let end = md_file.offset(&edit.range.end);
+            text.replace_range(start..end, &edit.new_text);
+        }
+        text
+    }
+
     #[test]
-    fn test_range_for_full_doc() {
-        let res = range_for_full_doc(10, 10);
-        assert_eq!(res.start.line, 0);
-        assert_eq!(res.start.character, 0);
-        assert_eq!(res.end.line, 10);
-        assert_eq!(res.end.character, 10);
+    fn test_formatting_minimal_edits() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        let doc = "# Title\n\nSome text.\n\n* one\n* two\n\nMore text.\nLast line";
+        state.set_buffer(&uri, doc.to_string());
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        };
+        let edits = formatting(&params, &state).unwrap();
+        let line = |line| Position { line, character: 0 };
+        assert_eq!(
+            edits,
+            vec![
+                TextEdit {
+                    range: Range::new(line(4), Position::new(4, 1)),
+                    new_text: "-".to_string(),
+                },
+                TextEdit {
+                    range: Range::new(line(5), Position::new(5, 1)),
+                    new_text: "-".to_string(),
+                },
+                TextEdit {
+                    range: Range::new(Position::new(7, 10), line(8)),
+                    new_text: " ".to_string(),
+                },
+                TextEdit {
+                    range: Range::new(Position::new(8, 9), Position::new(8, 9)),
+                    new_text: "\n".to_string(),
+                },
+            ]
+        );
+        let md_file = state.md_files.get(&uri).unwrap();
+        let config = markdown_config(&uri, &state);
+        assert_eq!(
+            Some(apply_edits(md_file, &edits)),
+            format_md(doc, &config, &CodeBlockSettings::default(), None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_format_nested_list() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_client_settings(serde_json::json!({ "formatting": { "lineWidth": 40 } }));
+        let doc = "* one\n    * nested with a long line that is wrapped\n\n1. first\n    - sub\n";
+        state.set_buffer(&uri, doc.to_string());
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            // list items are indented by the width of their marker, not the tab size
+            options: FormattingOptions {
+                tab_size: 8,
+                insert_spaces: false,
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+        };
+        let edits = formatting(&params, &state).unwrap();
+        assert_eq!(
+            apply_edits(state.md_files.get(&uri).unwrap(), &edits),
+            "- one\n  - nested with a long line that is\n    wrapped\n\n1. first\n   - sub\n"
+        );
+    }
+
+    #[test]
+    fn test_range_formatting_in_container() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        let doc = "Some   text.\n\n* one\n  * nested\n    continued\n* two\n\nOther   text.\n";
+        state.set_buffer(&uri, doc.to_string());
+        let params = DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range::new(Position::new(3, 4), Position::new(4, 8)),
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        };
+        // only the nested item is changed, with the indentation of the list
+        let edits = range_formatting(&params, &state).unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                TextEdit {
+                    range: Range::new(Position::new(3, 2), Position::new(3, 3)),
+                    new_text: "-".to_string(),
+                },
+                TextEdit {
+                    range: Range::new(Position::new(3, 10), Position::new(4, 4)),
+                    new_text: " ".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_on_type_formatting() {
+        let uri = Url::parse("file:///doc.md").unwrap();
+        let mut state = State::new();
+        state.set_buffer(
+            &uri,
+            "> | a | long |\n> |-|-|\n> | xyz\t| 1 |\n".to_string(),
+        );
+        let params = DocumentOnTypeFormattingParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position::new(2, 8),
+            },
+            ch: "\t".to_string(),
+            options: FormattingOptions::default(),
+        };
+        assert_eq!(on_type_formatting(&params, &state), None);
+        state.set_client_settings(serde_json::json!({ "formatTablesOnType": true }));
+        assert_eq!(
+            on_type_formatting(&params, &state),
+            Some(vec![TextEdit {
+                range: Range::new(Position::new(0, 2), Position::new(2, 13)),
+                new_text: "| a   | long |\n> | --- | ---- |\n> | xyz | 1    |".to_string(),
+            }])
+        );
     }
 }
@@ -1,11 +1,11 @@
-use lsp_types::{
-    Hover, HoverContents, HoverParams, MarkupContent, MarkupKind, Position, Range, Url,
+use lsp_types::{Hover, HoverContents, HoverParams, MarkupContent, MarkupKind, Url};
+use markdown::{
+    mdast::{FootnoteReference, Heading, Link, LinkReference, Node},
+    unist,
 };
-use markdown::mdast::{FootnoteReference, Heading, Link, LinkReference, Node};
 
 use crate::{
     ast::{get_heading_text, TraverseNode},
-    definition::range_from_position,
     links::{resolve_link, ResolvedLink},
     state::State,
     symbols::add_pounds,
@@ -14,15 +14,15 @@ use crate::{
 pub fn hover(params: &HoverParams, state: &State) -> Option<Hover> {
     let position_params = &params.text_document_position_params;
     let req_uri = &position_params.text_document.uri;
-    let Position { line, character } = position_params.position;
 
     let req_ast = state.ast_for_uri(req_uri)?;
-    let node = req_ast.find_linkable_for_position(line, character)?;
+    let offset = state.offset_for_uri(req_uri, &position_params.position)?;
+    let node = req_ast.find_linkable_for_position(offset)?;
     log::info!("HOVERRRRRR NODE : {:#?}", node);
 
     let message = match node {
         Node::Heading(heading) => handle_heading(req_uri, heading, state),
-        Node::Link(link) => handle_link(link, state),
+        Node::Link(link) => handle_link(req_uri, link, state),
         Node::LinkReference(link_ref) => handle_link_reference(req_uri, link_ref, state),
         Node::FootnoteReference(foot_ref) => handle_footnote_reference(req_uri, foot_ref, state),
         _ => None,
@@ -41,7 +41,7 @@ fn handle_heading(req_uri: &Url, req_heading: &Heading, state: &State) -> Option
         let headings = ast.find_headings();
         headings.into_iter().fold(String::new(), |acc, heading| {
             if let Some(heading_text) = get_heading_text(heading) {
-                let mut outline = format!("{acc}\n{}", add_pounds(heading_text, heading.depth));
+                let mut outline = format!("{acc}\n{}", add_pounds(&heading_text, heading.depth));
                 if heading == req_heading {
                     outline.push_str(" `<--`")
                 }
@@ -53,8 +53,8 @@ fn handle_heading(req_uri: &Url, req_heading: &Heading, state: &State) -> Option
     })
 }
 
-fn handle_link(link: &Link, state: &State) -> Option<String> {
-    match resolve_link(link, state) {
+fn handle_link(req_uri: &Url, link: &Link, state: &State) -> Option<String> {
+    match resolve_link(link, req_uri, state) {
         ResolvedLink::File { file_uri, .. } => handle_link_other_file(file_uri, state),
         ResolvedLink::InternalHeading {
             file_uri, heading, ..
@@ -70,35 +70,13 @@ fn handle_link_heading(target_uri: &Url, heading: &Heading, state: &State) -> Op
     let target_ast = state.ast_for_uri(target_uri)?;
     let linked_heading_pos = heading.position.as_ref()?;
     let depth = heading.depth;
-    match target_ast.find_next_heading(linked_heading_pos.end.line, depth) {
-        Some(next_heading) => {
-            let next_heading_pos = next_heading.position.as_ref()?;
-            let range = Range {
-                start: Position {
-                    line: (linked_heading_pos.start.line - 1) as u32,
-                    character: (linked_heading_pos.start.column - 1) as u32,
-                },
-                end: Position {
-                    line: (next_heading_pos.start.line - 1) as u32,
-                    character: (next_heading_pos.end.column - 1) as u32,
-                },
-            };
-            state.buffer_range_for_uri(target_uri, &range)
-        }
-        None => {
-            let range = Range {
-                start: Position {
-                    line: (linked_heading_pos.start.line - 1) as u32,
-                    character: (linked_heading_pos.start.column - 1) as u32,
-                },
-                end: Position {
-                    line: 99999,
-                    character: 99999,
-                },
-            };
-            state.buffer_range_for_uri(target_uri, &range)
-        }
-    }
+    let end = match target_ast.find_next_heading(linked_heading_pos.end.line, depth) {
+        Some(next_heading) => next_heading.position.as_ref()?.end.offset,
+        None => state.buffer_for_uri(target_uri)?.len(),
+    };
+    state
+        .buffer_slice_for_uri(target_uri, linked_heading_pos.start.offset, end)
+        .map(ToString::to_string)
 }
 
 fn handle_link_other_file(target_uri: &Url, state: &State) -> Option<String> {
@@ -108,10 +86,9 @@ fn handle_link_other_file(target_uri: &Url, state: &State) -> Option<String> {
 fn handle_link_reference(req_uri: &Url, link_ref: &LinkReference, state: &State) -> Option<String> {
     let ast = state.ast_for_uri(req_uri)?;
     let def = ast.find_def_for_link_ref(link_ref)?;
-    def.position.as_ref().and_then(|pos| {
-        let range = range_from_position(pos);
-        state.buffer_range_for_uri(req_uri, &range)
-    })
+    def.position
+        .as_ref()
+        .and_then(|pos| buffer_slice_for_position(req_uri, pos, state))
 }
 
 fn handle_footnote_reference(
@@ -121,8 +98,18 @@ fn handle_footnote_reference(
 ) -> Option<String> {
     let ast = state.ast_for_uri(req_uri)?;
     let footnote_def_node = ast.find_footnote_def_for_footnote_ref(footnote_ref)?;
-    footnote_def_node.position.as_ref().and_then(|pos| {
-        let range = range_from_position(pos);
-        state.buffer_range_for_uri(req_uri, &range)
-    })
+    footnote_def_node
+        .position
+        .as_ref()
+        .and_then(|pos| buffer_slice_for_position(req_uri, pos, state))
+}
+
+fn buffer_slice_for_position(
+    uri: &Url,
+    position: &unist::Position,
+    state: &State,
+) -> Option<String> {
+    state
+        .buffer_slice_for_uri(uri, position.start.offset, position.end.offset)
+        .map(ToString::to_string)
 }
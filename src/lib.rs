@@ -1,13 +1,20 @@
 pub mod ast;
 pub mod code_actions;
+pub mod code_blocks;
 pub mod completion;
 pub mod definition;
 pub mod diagnostics;
+pub mod format_config;
 pub mod formatting;
 pub mod hover;
+pub mod line_index;
 pub mod links;
 pub mod references;
 pub mod rename;
+pub mod rename_files;
 pub mod server;
+pub mod settings;
+pub mod slug;
 pub mod state;
 pub mod symbols;
+pub mod table;
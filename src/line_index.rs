@@ -0,0 +1,176 @@
+use lsp_types::{Position, PositionEncodingKind, Range};
+use markdown::unist;
+
+/// Unit in which the `character` of a LSP `Position` is counted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the first of the client's encodings that is supported, falls back
+    /// to UTF-16, which every client must support
+    pub fn negotiate(client_encodings: Option<&[PositionEncodingKind]>) -> Self {
+        client_encodings
+            .into_iter()
+            .flatten()
+            .find_map(|kind| match kind.as_str() {
+                "utf-8" => Some(PositionEncoding::Utf8),
+                "utf-16" => Some(PositionEncoding::Utf16),
+                "utf-32" => Some(PositionEncoding::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn kind(&self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn char_len(&self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+}
+
+/// Maps byte offsets of a text (as used by `markdown::unist::Point`) to LSP
+/// positions and back
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    /// Byte offset of the start of each line
+    line_starts: Vec<usize>,
+    encoding: PositionEncoding,
+}
+
+impl LineIndex {
+    pub fn new(text: &str, encoding: PositionEncoding) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            line_starts,
+            encoding,
+        }
+    }
+
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding
+    }
+
+    /// Byte offset in `text` for `pos`, positions beyond the end of a line or
+    /// the text get clamped
+    pub fn offset(&self, text: &str, pos: &Position) -> usize {
+        let Some(&line_start) = self.line_starts.get(pos.line as usize) else {
+            return text.len();
+        };
+        let line = self.line_text(text, pos.line as usize);
+        let mut count = 0;
+        for (i, c) in line.char_indices() {
+            if count >= pos.character as usize {
+                return line_start + i;
+            }
+            count += self.encoding.char_len(c);
+        }
+        line_start + line.len()
+    }
+
+    /// LSP position for the byte `offset` in `text`
+    pub fn position(&self, text: &str, offset: usize) -> Position {
+        let offset = offset.min(text.len());
+        let line = self
+            .line_starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1);
+        let line_start = self.line_starts[line];
+        let character = text
+            .get(line_start..offset)
+            .map(|s| s.chars().map(|c| self.encoding.char_len(c)).sum())
+            .unwrap_or(offset - line_start);
+        Position {
+            line: line as u32,
+            character: character as u32,
+        }
+    }
+
+    /// LSP range between the byte offsets `start` and `end`
+    pub fn range(&self, text: &str, start: usize, end: usize) -> Range {
+        Range {
+            start: self.position(text, start),
+            end: self.position(text, end),
+        }
+    }
+
+    /// LSP range for a `markdown::unist::Position`
+    pub fn unist_range(&self, text: &str, position: &unist::Position) -> Range {
+        self.range(text, position.start.offset, position.end.offset)
+    }
+
+    /// Content of line number `line` (0-based) without its line ending
+    fn line_text<'a>(&self, text: &'a str, line: usize) -> &'a str {
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map(|next| next - 1)
+            .unwrap_or(text.len());
+        text[start..end].trim_end_matches('\r')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "# Überschrift\n\nEmoji 😀 and 漢字\n";
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+        let kinds = vec![PositionEncodingKind::UTF32, PositionEncodingKind::UTF8];
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&kinds)),
+            PositionEncoding::Utf32
+        );
+    }
+
+    #[test]
+    fn test_position_roundtrip() {
+        let offset = TEXT.find("and").unwrap();
+        for (encoding, character) in [
+            (PositionEncoding::Utf8, 11),
+            (PositionEncoding::Utf16, 9),
+            (PositionEncoding::Utf32, 8),
+        ] {
+            let line_index = LineIndex::new(TEXT, encoding);
+            let pos = line_index.position(TEXT, offset);
+            assert_eq!(pos, Position { line: 2, character });
+            assert_eq!(line_index.offset(TEXT, &pos), offset);
+        }
+    }
+
+    #[test]
+    fn test_offset_clamps() {
+        let line_index = LineIndex::new(TEXT, PositionEncoding::Utf16);
+        let end_of_heading = TEXT.find('\n').unwrap();
+        let pos = Position {
+            line: 0,
+            character: 999,
+        };
+        assert_eq!(line_index.offset(TEXT, &pos), end_of_heading);
+        let pos = Position {
+            line: 99,
+            character: 0,
+        };
+        assert_eq!(line_index.offset(TEXT, &pos), TEXT.len());
+    }
+}
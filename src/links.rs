@@ -1,13 +1,15 @@
+use std::path::{Component, Path, PathBuf};
+
 use lsp_types::Url;
 use markdown::{
-    mdast::{Heading, Link, Node, Text},
+    mdast::{Definition, Heading, Image, Link, Node, Text},
     unist::{Point, Position as AstPosition},
 };
 use regex::Regex;
 
 use crate::{
     ast::{get_heading_text, TraverseNode},
-    state::State,
+    state::{MdFile, State},
 };
 
 use percent_encoding::{AsciiSet, CONTROLS};
@@ -25,14 +27,14 @@ pub fn url_decode(s: &str) -> String {
         .to_string()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum MdLink<'a> {
     NormalLink(&'a Link),
     WikiLink(&'a Link),
 }
 
 impl<'a> MdLink<'a> {
-    fn new(link: &'a Link) -> Self {
+    pub fn new(link: &'a Link) -> Self {
         match &link.title {
             Some(title) if title == "wikilink" => MdLink::WikiLink(link),
             _ => MdLink::NormalLink(link),
@@ -45,6 +47,12 @@ impl<'a> MdLink<'a> {
         }
     }
 
+    pub fn url(&self) -> &str {
+        match self {
+            MdLink::NormalLink(link) | MdLink::WikiLink(link) => &link.url,
+        }
+    }
+
     pub fn title(&self) -> Option<&str> {
         match self {
             MdLink::NormalLink(link) | MdLink::WikiLink(link) => link.title.as_deref(),
@@ -59,6 +67,62 @@ impl<'a> MdLink<'a> {
     }
 }
 
+/// Byte offsets of the link target (the part, that becomes `Link::url`) in
+/// `buffer`, the source of the AST containing the link
+pub fn link_target_offsets(link: &MdLink, buffer: &str) -> Option<(usize, usize)> {
+    let pos = link.position()?;
+    match link {
+        MdLink::WikiLink(link) => {
+            let start = pos.start.offset + 2;
+            Some((start, start + link.url.len()))
+        }
+        MdLink::NormalLink(link) => {
+            // `[text](destination "title")`
+            let text_end = link
+                .children
+                .last()
+                .and_then(|child| child.position())
+                .map(|child_pos| child_pos.end.offset)
+                .unwrap_or(pos.start.offset + 1);
+            buffer
+                .get(text_end..pos.end.offset - 1)?
+                .strip_prefix("](")?;
+            destination_offsets(buffer, text_end + 2, pos.end.offset - 1)
+        }
+    }
+}
+
+/// Byte offsets of the url of `image` (`![alt](destination "title")`) in
+/// `buffer`
+pub fn image_target_offsets(image: &Image, buffer: &str) -> Option<(usize, usize)> {
+    let pos = image.position.as_ref()?;
+    let source = buffer.get(pos.start.offset..pos.end.offset)?;
+    let start = pos.start.offset + source.find("](")? + 2;
+    destination_offsets(buffer, start, pos.end.offset - 1)
+}
+
+/// Byte offsets of the url of `def` (`[label]: destination "title"`) in
+/// `buffer`
+pub fn definition_target_offsets(def: &Definition, buffer: &str) -> Option<(usize, usize)> {
+    let pos = def.position.as_ref()?;
+    let source = buffer.get(pos.start.offset..pos.end.offset)?;
+    let start = pos.start.offset + source.find("]:")? + 2;
+    destination_offsets(buffer, start, pos.end.offset)
+}
+
+/// Offsets of the destination between `start` and `end`, without the `<>` of
+/// an enclosed destination and without the title
+fn destination_offsets(buffer: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    let destination = buffer.get(start..end)?;
+    let trimmed = destination.trim_start();
+    let start = start + destination.len() - trimmed.len();
+    let len = match trimmed.strip_prefix('<') {
+        Some(enclosed) => return Some((start + 1, start + 1 + enclosed.find('>')?)),
+        None => trimmed.find(char::is_whitespace).unwrap_or(trimmed.len()),
+    };
+    Some((start, start + len))
+}
+
 #[derive(Debug)]
 pub enum ResolvedLink<'a> {
     File {
@@ -80,6 +144,18 @@ pub enum ResolvedLink<'a> {
         /// The Heading, the Link links to
         heading: &'a Heading,
     },
+    /// Link to a non-markdown file
+    Asset {
+        link: MdLink<'a>,
+        /// The Uri of the file, the Link links to
+        file_uri: &'a Url,
+    },
+    /// Wikilink matching several files by basename or path suffix
+    Ambiguous {
+        link: MdLink<'a>,
+        /// The Uris of all matching files
+        candidates: Vec<&'a Url>,
+    },
     Http,
     Unresolved,
 }
@@ -90,7 +166,9 @@ impl ResolvedLink<'_> {
         match self {
             ResolvedLink::File { link, .. }
             | ResolvedLink::InternalHeading { link, .. }
-            | ResolvedLink::ExternalHeading { link, .. } => link.position(),
+            | ResolvedLink::ExternalHeading { link, .. }
+            | ResolvedLink::Asset { link, .. }
+            | ResolvedLink::Ambiguous { link, .. } => link.position(),
             _ => None,
         }
     }
@@ -100,13 +178,14 @@ impl ResolvedLink<'_> {
         match self {
             ResolvedLink::File { file_uri, .. }
             | ResolvedLink::InternalHeading { file_uri, .. }
-            | ResolvedLink::ExternalHeading { file_uri, .. } => Some(file_uri),
+            | ResolvedLink::ExternalHeading { file_uri, .. }
+            | ResolvedLink::Asset { file_uri, .. } => Some(file_uri),
             _ => None,
         }
     }
 
     /// If Link links to a heading, return the text of this heading
-    pub fn heading_text(&self) -> Option<&str> {
+    pub fn heading_text(&self) -> Option<String> {
         match self {
             ResolvedLink::InternalHeading { heading, .. }
             | ResolvedLink::ExternalHeading { heading, .. } => get_heading_text(heading),
@@ -115,7 +194,8 @@ impl ResolvedLink<'_> {
     }
 }
 
-pub fn resolve_link<'a>(link: &'a Link, state: &'a State) -> ResolvedLink<'a> {
+/// Resolves `link`, contained in the file `req_uri`
+pub fn resolve_link<'a>(link: &'a Link, req_uri: &Url, state: &'a State) -> ResolvedLink<'a> {
     let md_link = MdLink::new(link);
 
     if link.url.starts_with("http") {
@@ -124,116 +204,327 @@ pub fn resolve_link<'a>(link: &'a Link, state: &'a State) -> ResolvedLink<'a> {
 
     match link.url.split_once('#') {
         Some(("", _)) => {
-            for (url, md_file) in state.md_files.iter() {
-                if let Some(heading) = &md_file.ast.find_heading_for_link(link) {
-                    return ResolvedLink::InternalHeading {
-                        link: md_link,
-                        file_uri: url,
-                        heading,
-                    };
-                }
+            let Some((file_uri, md_file)) = state.md_files.get_key_value(req_uri) else {
+                return ResolvedLink::Unresolved;
+            };
+            match md_file.ast.find_heading_for_link(link) {
+                Some(heading) => ResolvedLink::InternalHeading {
+                    link: md_link,
+                    file_uri,
+                    heading,
+                },
+                None => ResolvedLink::Unresolved,
             }
-            ResolvedLink::Unresolved
         }
         // link with referece to heading `...#...`
         Some((file_ref_text, heading_ref_text)) => {
-            let file = match md_link {
-                MdLink::NormalLink(_) => url_decode(file_ref_text),
-                MdLink::WikiLink(_) => {
-                    // allow both for wikilinks: with suffix and without
-                    if file_ref_text.ends_with(".md") {
-                        file_ref_text.into()
-                    } else {
-                        format!("{}.md", file_ref_text)
-                    }
-                }
+            // e.g. `doc.pdf#page=2`
+            if let Some(file_uri) = resolve_asset(file_ref_text, req_uri, state) {
+                return ResolvedLink::Asset {
+                    link: md_link,
+                    file_uri,
+                };
+            }
+            let files = match md_link {
+                MdLink::NormalLink(_) => vec![url_decode(file_ref_text)],
+                // allow both for wikilinks: with suffix and without
+                MdLink::WikiLink(_) => with_md_suffix(file_ref_text, state),
             };
 
-            for (url, relative_path) in state.get_file_list() {
-                if relative_path == file {
-                    // as we get url from state it must be in there
-                    let ast = state.ast_for_uri(url).unwrap();
-                    if let Some(heading) = ast.find_heading_for_link_identifier(heading_ref_text) {
-                        return ResolvedLink::ExternalHeading {
-                            link: md_link,
-                            file_uri: url,
-                            heading,
-                        };
-                    }
-                    return ResolvedLink::File {
-                        link: md_link,
-                        file_uri: url,
-                    };
-                }
+            let (url, md_file) = match find_linked_file(&files, &md_link, req_uri, state) {
+                Ok(linked_file) => linked_file,
+                Err(resolved_link) => return resolved_link,
+            };
+            match md_file
+                .ast
+                .find_heading_for_link_identifier(heading_ref_text)
+            {
+                Some(heading) => ResolvedLink::ExternalHeading {
+                    link: md_link,
+                    file_uri: url,
+                    heading,
+                },
+                None => ResolvedLink::File {
+                    link: md_link,
+                    file_uri: url,
+                },
             }
-            ResolvedLink::Unresolved
         }
         None => {
-            let file = if link.url.ends_with(".md") {
-                link.url.to_string()
-            } else {
-                format!("{}.md", link.url)
+            if let Some(file_uri) = resolve_asset(&link.url, req_uri, state) {
+                return ResolvedLink::Asset {
+                    link: md_link,
+                    file_uri,
+                };
+            }
+            let files = match md_link {
+                MdLink::NormalLink(_) => with_md_suffix(&url_decode(&link.url), state),
+                MdLink::WikiLink(_) => with_md_suffix(&link.url, state),
             };
-            for (url, relative_path) in state.get_file_list() {
-                if relative_path == file {
-                    return ResolvedLink::File {
-                        link: md_link,
-                        file_uri: url,
-                    };
-                }
+            match find_linked_file(&files, &md_link, req_uri, state) {
+                Ok((url, _)) => ResolvedLink::File {
+                    link: md_link,
+                    file_uri: url,
+                },
+                Err(resolved_link) => resolved_link,
+            }
+        }
+    }
+}
+
+/// The asset file (e.g. an image) `url` in the file `req_uri` points to
+pub fn resolve_asset<'a>(url: &str, req_uri: &Url, state: &'a State) -> Option<&'a Url> {
+    let path = resolve_link_path(&url_decode(url), req_uri, state)?;
+    let uri = Url::from_file_path(path).ok()?;
+    state.asset_file(&uri)
+}
+
+/// Whether `url` has a scheme, like `https://...` or `data:...`, and so
+/// doesn't point to a file in the workspace
+pub fn is_external_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| url.scheme().len() > 1)
+}
+
+/// `file` if it has a markdown file extension, otherwise `file` with each of
+/// the markdown file extensions appended
+fn with_md_suffix(file: &str, state: &State) -> Vec<String> {
+    if state.is_md_file(Path::new(file)) {
+        vec![file.to_string()]
+    } else {
+        state
+            .settings()
+            .file_extensions
+            .iter()
+            .map(|ext| format!("{file}.{ext}"))
+            .collect()
+    }
+}
+
+/// The file one of `files` links to. Wikilinks that can't be resolved by path
+/// match all files ending with the file, like `[[meeting-notes]]` for
+/// `/notes/2024/meeting-notes.md`. Unresolved or ambiguous links are returned
+/// as error.
+fn find_linked_file<'a>(
+    files: &[String],
+    md_link: &MdLink<'a>,
+    req_uri: &Url,
+    state: &'a State,
+) -> Result<(&'a Url, &'a MdFile), ResolvedLink<'a>> {
+    let linked_file = files.iter().find_map(|file| {
+        let path = resolve_link_path(file, req_uri, state)?;
+        let uri = Url::from_file_path(path).ok()?;
+        state.md_files.get_key_value(&uri)
+    });
+    if let Some(linked_file) = linked_file {
+        return Ok(linked_file);
+    }
+    if !md_link.is_wikilink() {
+        return Err(ResolvedLink::Unresolved);
+    }
+    let mut candidates: Vec<(&Url, &MdFile)> = files
+        .iter()
+        .flat_map(|file| wikilink_candidates(file, req_uri, state))
+        .collect();
+    candidates.sort_by_key(|(uri, _)| uri.as_str());
+    candidates.dedup_by_key(|(uri, _)| *uri);
+    match candidates.len() {
+        0 => Err(ResolvedLink::Unresolved),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(ResolvedLink::Ambiguous {
+            link: *md_link,
+            candidates: candidates.into_iter().map(|(uri, _)| uri).collect(),
+        }),
+    }
+}
+
+/// Files in the workspace folder of `req_uri` whose path ends with `file`
+fn wikilink_candidates<'a>(
+    file: &str,
+    req_uri: &Url,
+    state: &'a State,
+) -> Vec<(&'a Url, &'a MdFile)> {
+    let file = Path::new(file);
+    if file.has_root()
+        || file
+            .components()
+            .any(|c| matches!(c, Component::CurDir | Component::ParentDir))
+    {
+        return Vec::new();
+    }
+    let root = state.workspace_root_for_uri(req_uri);
+    let mut candidates: Vec<(&Url, &MdFile)> = state
+        .md_files
+        .iter()
+        .filter(|(uri, _)| {
+            uri.to_file_path().is_ok_and(|path| {
+                path.ends_with(file) && root.as_ref().is_none_or(|root| path.starts_with(root))
+            })
+        })
+        .collect();
+    candidates.sort_by_key(|(uri, _)| uri.as_str());
+    candidates
+}
+
+/// Shortest link target (without markdown file extension) that unambiguously resolves to
+/// `target_uri` from the wikilinks in `req_uri`
+pub fn shortest_wikilink_target(target_uri: &Url, req_uri: &Url, state: &State) -> Option<String> {
+    let path = target_uri.to_file_path().ok()?;
+    let root = state.workspace_root_for_uri(req_uri)?;
+    let md_paths: Vec<PathBuf> = state
+        .md_files
+        .keys()
+        .filter_map(|uri| uri.to_file_path().ok())
+        .collect();
+    shortest_wikilink_target_in(&path, &root, &md_paths, state)
+}
+
+/// Shortest wikilink target for `path` in the workspace folder `root`, if its
+/// markdown files were `md_paths`, e.g. after renaming files
+pub fn shortest_wikilink_target_in(
+    path: &Path,
+    root: &Path,
+    md_paths: &[PathBuf],
+    state: &State,
+) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let components: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    (1..=components.len()).find_map(|n| {
+        let suffix = components[components.len() - n..].join("/");
+        let matches = md_paths
+            .iter()
+            .filter(|md_path| md_path.starts_with(root) && md_path.ends_with(&suffix))
+            .count();
+        (matches == 1).then(|| state.settings().strip_md_extension(&suffix).to_string())
+    })
+}
+
+/// Path of the file a link target (without heading) points to. Links starting
+/// with `/` are relative to the root of the workspace folder of `req_uri`, all
+/// others are relative to the directory of `req_uri`.
+pub fn resolve_link_path(file: &str, req_uri: &Url, state: &State) -> Option<PathBuf> {
+    let path = match file.strip_prefix('/') {
+        Some(root_relative) => state.workspace_root_for_uri(req_uri)?.join(root_relative),
+        None => req_uri.to_file_path().ok()?.parent()?.join(file),
+    };
+    Some(normalize_path(&path))
+}
+
+/// Removes `.` and `..` segments without touching the file system
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
             }
-            ResolvedLink::Unresolved
+            other => normalized.push(other),
         }
     }
+    normalized
+}
+
+/// Path of `to` relative to the directory `from_dir`, as used in links
+pub fn relative_link_path(from_dir: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    std::iter::repeat_n("..".to_string(), from.len() - common)
+        .chain(
+            to[common..]
+                .iter()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect::<Vec<_>>()
+        .join("/")
 }
 
 #[derive(Debug, PartialEq, Eq)]
 struct ExtractedWikiLink {
     content: String,
+    /// Column of the content in its line, 1-based
     start_position: usize,
     line_number: usize,
+    /// Byte offset of the content in the input
+    offset: usize,
 }
 
 impl ExtractedWikiLink {
-    fn link_text_node(&self, start_line: usize) -> Node {
-        let value = "".to_string(); // TODO parse Wikilinks with `|` (everything after is value)
-        let value_len = value.len();
+    /// Point of the start of the content, `start` is the point of the input
+    fn content_start(&self, start: &Point) -> Point {
+        let column = if self.line_number == 0 {
+            start.column + self.start_position - 1
+        } else {
+            self.start_position
+        };
+        Point {
+            line: start.line + self.line_number,
+            column,
+            offset: start.offset + self.offset,
+        }
+    }
+
+    /// The link target, the part before `|`. In tables the `|` is escaped
+    /// as `\|`
+    fn target(&self) -> &str {
+        match self.content.split_once('|') {
+            Some((target, _)) => target.strip_suffix('\\').unwrap_or(target),
+            None => &self.content,
+        }
+    }
+
+    /// The alias after `|` and its byte index in the content
+    fn alias(&self) -> Option<(usize, &str)> {
+        let index = self.content.find('|')?;
+        Some((index + 1, &self.content[index + 1..]))
+    }
+
+    /// Text node with the alias of `[[target|alias]]`, empty for wikilinks
+    /// without alias
+    fn link_text_node(&self, start: &Point) -> Node {
+        let content_start = self.content_start(start);
+        let (alias_index, value) = self.alias().unwrap_or((0, ""));
+        let text_start = Point {
+            line: content_start.line,
+            column: content_start.column + alias_index,
+            offset: content_start.offset + alias_index,
+        };
         let link_text = Text {
-            value,
+            value: value.to_string(),
             position: Some(AstPosition {
-                start: Point {
-                    line: start_line + self.line_number,
-                    column: self.start_position,
-                    offset: self.start_position,
-                },
+                start: text_start.clone(),
                 end: Point {
-                    line: start_line + self.line_number,
-                    column: self.start_position + value_len,
-                    offset: self.start_position + value_len,
+                    line: text_start.line,
+                    column: text_start.column + value.len(),
+                    offset: text_start.offset + value.len(),
                 },
             }),
         };
         Node::Text(link_text)
     }
 
-    fn link_node(&self, start_line: usize) -> Node {
-        let link_text_node = self.link_text_node(start_line);
+    fn link_node(&self, start: &Point) -> Node {
+        let link_text_node = self.link_text_node(start);
+        let content_start = self.content_start(start);
 
         let link = Link {
             children: vec![link_text_node],
             position: Some(AstPosition {
                 start: Point {
-                    line: start_line + self.line_number,
-                    column: self.start_position - 2,
-                    offset: self.start_position - 2,
+                    line: content_start.line,
+                    column: content_start.column - 2,
+                    offset: content_start.offset - 2,
                 },
                 end: Point {
-                    line: start_line + self.line_number,
-                    column: self.start_position + self.content.len() + 2,
-                    offset: self.start_position + self.content.len() + 2,
+                    line: content_start.line,
+                    column: content_start.column + self.content.len() + 2,
+                    offset: content_start.offset + self.content.len() + 2,
                 },
             }),
-            url: self.content.clone(),
+            url: self.target().to_string(),
             title: Some("wikilink".to_string()),
         };
         Node::Link(link)
@@ -243,32 +534,41 @@ impl ExtractedWikiLink {
 fn extract_wiki_links(input: &str) -> Vec<ExtractedWikiLink> {
     let re = Regex::new(r"\[\[([\s\S]*?)\]\]").unwrap();
 
-    input
-        .lines()
-        .enumerate()
-        .flat_map(|(line_number, line)| {
-            re.captures_iter(line).filter_map(move |captures| {
-                captures.get(1).map(|content| ExtractedWikiLink {
+    let mut line_start = 0;
+    let mut extracted = Vec::new();
+    for (line_number, line) in input.split('\n').enumerate() {
+        for captures in re.captures_iter(line) {
+            if let Some(content) = captures.get(1) {
+                extracted.push(ExtractedWikiLink {
                     content: content.as_str().to_string(),
                     start_position: content.start() + 1,
                     line_number,
+                    offset: line_start + content.start(),
                 })
-            })
-        })
-        .collect()
+            }
+        }
+        line_start += line.len() + 1;
+    }
+    extracted
 }
 
-pub fn parse_wiki_links(node: &mut Node) {
+/// Adds `Link` nodes for wikilinks found in text nodes. `source` is the text
+/// the AST was parsed from.
+pub fn parse_wiki_links(node: &mut Node, source: &str) {
     let mut links = Vec::new();
     if let Some(children) = node.children() {
         for child in children {
             if let Node::Text(t) = child {
                 if t.value.contains("[[") && t.value.contains("]]") {
-                    let t_position = t.position.as_ref().unwrap().clone();
-                    let extracted = extract_wiki_links(&t.value);
+                    let t_position = t.position.as_ref().unwrap();
+                    // the source is needed for correct offsets, value might be unescaped
+                    let text = source
+                        .get(t_position.start.offset..t_position.end.offset)
+                        .unwrap_or(&t.value);
+                    let extracted = extract_wiki_links(text);
 
                     for i in extracted {
-                        let link_ast = i.link_node(t_position.start.line);
+                        let link_ast = i.link_node(&t_position.start);
                         links.push(link_ast);
                     }
                 }
@@ -281,7 +581,7 @@ pub fn parse_wiki_links(node: &mut Node) {
     // recurse through children
     if let Some(children) = node.children_mut() {
         for child in children {
-            parse_wiki_links(child);
+            parse_wiki_links(child, source);
         }
     }
 }
@@ -290,6 +590,173 @@ pub fn parse_wiki_links(node: &mut Node) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_link_target_offsets() {
+        let source =
+            "See [the *docs*](/docs/a%20b.md#intro \"Docs\") and [[notes#Heading]] [[a|b]]\n";
+        let mut ast = markdown::to_mdast(source, &markdown::ParseOptions::gfm()).unwrap();
+        parse_wiki_links(&mut ast, source);
+        let targets: Vec<&str> = ast
+            .find_links()
+            .into_iter()
+            .filter_map(|link| link_target_offsets(&MdLink::new(link), source))
+            .map(|(start, end)| &source[start..end])
+            .collect();
+        assert_eq!(targets, vec!["/docs/a%20b.md#intro", "notes#Heading", "a"]);
+    }
+
+    #[test]
+    fn test_parse_wiki_link_alias() {
+        let source = "| [[Design Doc#Goals\\|the design]] |\n|---|\n\n[[Design Doc|the design]]\n";
+        let mut ast = markdown::to_mdast(source, &markdown::ParseOptions::gfm()).unwrap();
+        parse_wiki_links(&mut ast, source);
+        let links = ast.find_links();
+        let parsed: Vec<(&str, &str, &str)> = links
+            .iter()
+            .map(|link| {
+                let Some(Node::Text(text)) = link.children.first() else {
+                    panic!("wikilink without text node");
+                };
+                let pos = text.position.as_ref().unwrap();
+                (
+                    link.url.as_str(),
+                    text.value.as_str(),
+                    &source[pos.start.offset..pos.end.offset],
+                )
+            })
+            .collect();
+        assert_eq!(
+            parsed,
+            vec![
+                ("Design Doc#Goals", "the design", "the design"),
+                ("Design Doc", "the design", "the design"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_relative_link() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        let guide_uri = Url::from_file_path(root.join("docs/guide/intro.md")).unwrap();
+        let auth_uri = Url::from_file_path(root.join("docs/api/auth.md")).unwrap();
+        state.set_buffer(&auth_uri, "# Auth\n\n## Tokens\n".to_string());
+        state.set_buffer(
+            &guide_uri,
+            "[tokens](../api/auth.md#tokens) [auth](./../api/auth) [missing](auth.md)\n"
+                .to_string(),
+        );
+        let ast = state.ast_for_uri(&guide_uri).unwrap();
+        let resolved: Vec<ResolvedLink> = ast
+            .find_links()
+            .into_iter()
+            .map(|link| resolve_link(link, &guide_uri, &state))
+            .collect();
+        assert!(matches!(resolved[0], ResolvedLink::ExternalHeading { .. }));
+        assert_eq!(resolved[1].file_uri(), Some(&auth_uri));
+        assert!(matches!(resolved[2], ResolvedLink::Unresolved));
+    }
+
+    #[test]
+    fn test_resolve_wikilink_by_basename() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(lsp_types::WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        let index_uri = Url::from_file_path(root.join("index.md")).unwrap();
+        let notes_uri = Url::from_file_path(root.join("notes/2024/meeting-notes.md")).unwrap();
+        let a_uri = Url::from_file_path(root.join("a/todo.md")).unwrap();
+        let b_uri = Url::from_file_path(root.join("b/todo.md")).unwrap();
+        state.set_buffer(&notes_uri, "# Agenda\n".to_string());
+        state.set_buffer(&a_uri, "# A\n".to_string());
+        state.set_buffer(&b_uri, "# B\n".to_string());
+        state.set_buffer(
+            &index_uri,
+            "[[meeting-notes#Agenda]] [[2024/meeting-notes]] [[todo]] [[b/todo]]\n".to_string(),
+        );
+        let ast = state.ast_for_uri(&index_uri).unwrap();
+        let resolved: Vec<ResolvedLink> = ast
+            .find_links()
+            .into_iter()
+            .map(|link| resolve_link(link, &index_uri, &state))
+            .collect();
+        assert!(
+            matches!(resolved[0], ResolvedLink::ExternalHeading { file_uri, .. } if *file_uri == notes_uri)
+        );
+        assert!(
+            matches!(resolved[1], ResolvedLink::File { file_uri, .. } if *file_uri == notes_uri)
+        );
+        assert!(
+            matches!(&resolved[2], ResolvedLink::Ambiguous { candidates, .. } if *candidates == vec![&a_uri, &b_uri])
+        );
+        assert!(matches!(resolved[3], ResolvedLink::File { file_uri, .. } if *file_uri == b_uri));
+        assert_eq!(
+            shortest_wikilink_target(&notes_uri, &index_uri, &state),
+            Some("meeting-notes".to_string())
+        );
+        assert_eq!(
+            shortest_wikilink_target(&a_uri, &index_uri, &state),
+            Some("a/todo".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_link_with_configured_extensions() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(lsp_types::WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        assert!(state.set_client_settings(serde_json::json!({
+            "fileExtensions": ["md", "markdown"]
+        })));
+        let index_uri = Url::from_file_path(root.join("index.md")).unwrap();
+        let notes_uri = Url::from_file_path(root.join("notes/setup.markdown")).unwrap();
+        state.set_buffer(&notes_uri, "# Install\n".to_string());
+        state.set_buffer(
+            &index_uri,
+            "[setup](notes/setup) [notes](notes/setup.txt) [[setup#Install]]\n".to_string(),
+        );
+        let ast = state.ast_for_uri(&index_uri).unwrap();
+        let resolved: Vec<ResolvedLink> = ast
+            .find_links()
+            .into_iter()
+            .map(|link| resolve_link(link, &index_uri, &state))
+            .collect();
+        assert!(
+            matches!(resolved[0], ResolvedLink::File { file_uri, .. } if *file_uri == notes_uri)
+        );
+        assert!(matches!(resolved[1], ResolvedLink::Unresolved));
+        assert!(
+            matches!(resolved[2], ResolvedLink::ExternalHeading { file_uri, .. } if *file_uri == notes_uri)
+        );
+        assert_eq!(
+            shortest_wikilink_target(&notes_uri, &index_uri, &state),
+            Some("setup".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(Path::new("/docs/guide/./../api/auth.md")),
+            PathBuf::from("/docs/api/auth.md")
+        );
+    }
+
+    #[test]
+    fn test_relative_link_path() {
+        let to = Path::new("/docs/api/auth.md");
+        assert_eq!(relative_link_path(Path::new("/docs/api"), to), "auth.md");
+        assert_eq!(
+            relative_link_path(Path::new("/docs/guide"), to),
+            "../api/auth.md"
+        );
+    }
+
     #[test]
     fn test_extract_wiki_links() {
         let link_content_1 = "link content 1".to_string();
@@ -303,11 +770,13 @@ mod tests {
             content: link_content_1,
             start_position: 3,
             line_number: 0,
+            offset: 2,
         };
         let expected_2 = ExtractedWikiLink {
             content: link_content_2,
             start_position: 3,
             line_number: 4,
+            offset: 34,
         };
         assert_eq!(extracted[0], expected_1);
         assert_eq!(extracted[1], expected_2);
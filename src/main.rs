@@ -5,11 +5,16 @@ use clap::{ArgAction, Parser};
 use log::LevelFilter;
 use lsp_server::Connection;
 use lsp_types::{
-    CodeActionProviderCapability, HoverProviderCapability, InitializeParams, InitializeResult,
-    OneOf, RenameOptions, ServerCapabilities, ServerInfo, TextDocumentSyncCapability,
-    TextDocumentSyncKind, WorkDoneProgressOptions,
+    CodeActionKind, CodeActionOptions, CodeActionProviderCapability, DiagnosticOptions,
+    DiagnosticServerCapabilities, DocumentOnTypeFormattingOptions, FileOperationFilter,
+    FileOperationPattern, FileOperationRegistrationOptions, HoverProviderCapability,
+    InitializeParams, InitializeResult, OneOf, RenameOptions, ResourceOperationKind,
+    ServerCapabilities, ServerInfo, TextDocumentSyncCapability, TextDocumentSyncKind,
+    WorkDoneProgressOptions, WorkspaceFileOperationsServerCapabilities,
+    WorkspaceFoldersServerCapabilities, WorkspaceServerCapabilities,
 };
-use md_lsp::{server::Server, state::State};
+use md_lsp::{line_index::PositionEncoding, server::Server, settings::Settings, state::State};
+use serde_json::Value;
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
@@ -34,25 +39,73 @@ fn main() -> Result<()> {
     let (connection, io_threads) = Connection::stdio();
 
     let (id, params) = connection.initialize_start()?;
+    let init_params: InitializeParams = serde_json::from_value(params.clone())?;
+    let position_encoding = PositionEncoding::negotiate(
+        init_params
+            .capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_deref()),
+    );
+
+    // the capability can't change later, so the setting is only read from the
+    // initialization options
+    let format_tables_on_type = Settings::merged(
+        init_params
+            .initialization_options
+            .as_ref()
+            .unwrap_or(&Value::Null),
+        &Value::Null,
+    )
+    .format_tables_on_type;
 
     // Run the server and wait for the two threads to end (typically by trigger LSP Exit event).
     let initialize_result = InitializeResult {
         capabilities: ServerCapabilities {
+            position_encoding: Some(position_encoding.kind()),
             definition_provider: Some(OneOf::Left(true)),
             hover_provider: Some(HoverProviderCapability::Simple(true)),
-            text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                TextDocumentSyncKind::INCREMENTAL,
+            )),
             references_provider: Some(OneOf::Left(true)),
             document_symbol_provider: Some(OneOf::Left(true)),
             workspace_symbol_provider: Some(OneOf::Left(true)),
             document_formatting_provider: Some(OneOf::Left(true)),
             document_range_formatting_provider: Some(OneOf::Left(true)),
+            // realigns tables
+            document_on_type_formatting_provider: format_tables_on_type.then(|| {
+                DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "|".to_string(),
+                    more_trigger_character: Some(vec!["\t".to_string()]),
+                }
+            }),
             rename_provider: Some(OneOf::Right(RenameOptions {
                 prepare_provider: Some(true),
                 work_done_progress_options: WorkDoneProgressOptions {
                     work_done_progress: None,
                 },
             })),
-            code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+            code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                code_action_kinds: Some(vec![
+                    CodeActionKind::QUICKFIX,
+                    CodeActionKind::REFACTOR_REWRITE,
+                    CodeActionKind::SOURCE,
+                ]),
+                work_done_progress_options: WorkDoneProgressOptions {
+                    work_done_progress: None,
+                },
+                // document- and workspace-wide edits are computed on resolve
+                resolve_provider: Some(true),
+            })),
+            diagnostic_provider: Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                identifier: Some("md-lsp".to_string()),
+                inter_file_dependencies: true,
+                workspace_diagnostics: true,
+                work_done_progress_options: WorkDoneProgressOptions {
+                    work_done_progress: None,
+                },
+            })),
             completion_provider: Some(lsp_types::CompletionOptions {
                 resolve_provider: Some(false),
                 trigger_characters: Some(vec![
@@ -68,6 +121,27 @@ fn main() -> Result<()> {
                 all_commit_characters: None,
                 completion_item: None,
             }),
+            workspace: Some(WorkspaceServerCapabilities {
+                workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                    supported: Some(true),
+                    change_notifications: Some(OneOf::Left(true)),
+                }),
+                file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                    will_rename: Some(FileOperationRegistrationOptions {
+                        filters: vec![FileOperationFilter {
+                            scheme: Some("file".to_string()),
+                            pattern: FileOperationPattern {
+                                // markdown file extensions are configurable
+                                glob: "**/*".to_string(),
+                                // files and folders
+                                matches: None,
+                                options: None,
+                            },
+                        }],
+                    }),
+                    ..Default::default()
+                }),
+            }),
             ..Default::default()
         },
         server_info: Some(ServerInfo {
@@ -84,7 +158,7 @@ fn main() -> Result<()> {
         return Err(e.into());
     }
 
-    main_loop(connection, params)?;
+    main_loop(connection, init_params, position_encoding)?;
     io_threads.join()?;
 
     // Shut down gracefully.
@@ -135,23 +209,89 @@ fn init_logging(args: &Args) {
     logger.apply().expect("Failed to initialize logging");
 }
 
-fn main_loop(connection: Connection, params: serde_json::Value) -> Result<()> {
+fn main_loop(
+    connection: Connection,
+    params: InitializeParams,
+    position_encoding: PositionEncoding,
+) -> Result<()> {
     log::info!("Starting main loop");
-    let params: InitializeParams = serde_json::from_value(params)?;
     log::info!("INIT PARAMS: {:#?}", &params);
     let work_space_folders = params.workspace_folders;
+    let watch_files = params
+        .capabilities
+        .workspace
+        .as_ref()
+        .and_then(|ws| ws.did_change_watched_files.as_ref())
+        .and_then(|watched| watched.dynamic_registration)
+        .unwrap_or(false);
 
     // else is single file mode, I guess
     let mut state = State::new();
-    let server = Server::new(connection);
+    state.set_position_encoding(position_encoding);
+    state.set_code_action_resolve(
+        params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.code_action.as_ref())
+            .and_then(|code_action| code_action.resolve_support.as_ref())
+            .is_some_and(|resolve| resolve.properties.iter().any(|p| p == "edit")),
+    );
+    let workspace_edit = params
+        .capabilities
+        .workspace
+        .as_ref()
+        .and_then(|ws| ws.workspace_edit.as_ref());
+    state.set_create_files(workspace_edit.is_some_and(|workspace_edit| {
+        workspace_edit.document_changes == Some(true)
+            && workspace_edit
+                .resource_operations
+                .as_ref()
+                .is_some_and(|operations| operations.contains(&ResourceOperationKind::Create))
+    }));
+    if let Some(initialization_options) = params.initialization_options {
+        state.set_client_settings(initialization_options);
+    }
+    let mut server = Server::new(connection);
+    if params
+        .capabilities
+        .workspace
+        .as_ref()
+        .and_then(|ws| ws.configuration)
+        .unwrap_or(false)
+    {
+        server.enable_workspace_configuration();
+    }
+    if params
+        .capabilities
+        .text_document
+        .as_ref()
+        .is_some_and(|text_document| text_document.diagnostic.is_some())
+    {
+        let refresh_support = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|ws| ws.diagnostic.as_ref())
+            .and_then(|diagnostic| diagnostic.refresh_support)
+            .unwrap_or(false);
+        server.enable_pull_diagnostics(refresh_support);
+    }
+    if watch_files {
+        server.register_file_watchers()?;
+    }
 
     if let Some(wsf) = work_space_folders {
+        for folder in &wsf {
+            state.add_workspace_folder(folder.clone());
+        }
+        state.load_project_settings();
         state.index_md_files(&wsf);
-        state.set_workspace_folder(wsf[0].clone());
         for uri in state.md_files.keys() {
             server.handle_diagnostic(uri, &state)?;
         }
     }
 
+    server.request_configuration()?;
     server.run(state)
 }
@@ -1,23 +1,23 @@
-use lsp_types::{Location, Position as LspPosition, ReferenceParams, Url};
+use lsp_types::{Location, ReferenceParams, Url};
 use markdown::mdast::{Definition, FootnoteDefinition, Heading, Node};
 
 use crate::ast::TraverseNode;
 use crate::links::{resolve_link, ResolvedLink};
-use crate::{definition::range_from_position, state::State};
+use crate::state::State;
 
 pub fn references(params: &ReferenceParams, state: &State) -> Option<Vec<Location>> {
     let text_document_params = &params.text_document_position;
     let req_uri = &text_document_params.text_document.uri;
-    let LspPosition { line, character } = text_document_params.position;
 
     let req_ast = state.ast_for_uri(req_uri).unwrap();
-    let node = req_ast.find_definition_for_position(line, character);
+    let offset = state.offset_for_uri(req_uri, &text_document_params.position)?;
+    let node = req_ast.find_definition_for_position(offset);
 
     match node {
         Some(n) => match n {
             Node::Heading(h) => Some(handle_heading(h, state)),
-            Node::Definition(d) => handle_definition(req_ast, req_uri, d),
-            Node::FootnoteDefinition(f) => handle_footnote_definition(req_ast, req_uri, f),
+            Node::Definition(d) => handle_definition(req_ast, req_uri, d, state),
+            Node::FootnoteDefinition(f) => handle_footnote_definition(req_ast, req_uri, f, state),
             _ => None,
         },
         None => None,
@@ -29,7 +29,7 @@ fn handle_heading(heading: &Heading, state: &State) -> Vec<Location> {
         .into_iter()
         .filter_map(|(link_uri, resolved_link)| {
             let pos = resolved_link.link_position()?;
-            let range = range_from_position(pos);
+            let range = state.range_for_uri(link_uri, pos)?;
             Some(Location {
                 uri: link_uri.clone(),
                 range,
@@ -42,14 +42,17 @@ fn handle_definition(
     req_ast: &Node,
     req_uri: &Url,
     definition: &Definition,
+    state: &State,
 ) -> Option<Vec<Location>> {
     let link_refs = req_ast.find_link_references_for_identifier(&definition.identifier);
     link_refs
         .into_iter()
         .map(|link_ref| {
-            link_ref.position.as_ref().map(|pos| Location {
-                uri: req_uri.clone(),
-                range: range_from_position(pos),
+            link_ref.position.as_ref().and_then(|pos| {
+                Some(Location {
+                    uri: req_uri.clone(),
+                    range: state.range_for_uri(req_uri, pos)?,
+                })
             })
         })
         .collect()
@@ -59,14 +62,17 @@ fn handle_footnote_definition(
     req_ast: &Node,
     req_uri: &Url,
     fn_definition: &FootnoteDefinition,
+    state: &State,
 ) -> Option<Vec<Location>> {
     let footnote_refs = req_ast.find_footnote_references_for_identifier(&fn_definition.identifier);
     footnote_refs
         .into_iter()
         .map(|footnote_ref| {
-            footnote_ref.position.as_ref().map(|pos| Location {
-                uri: req_uri.clone(),
-                range: range_from_position(pos),
+            footnote_ref.position.as_ref().and_then(|pos| {
+                Some(Location {
+                    uri: req_uri.clone(),
+                    range: state.range_for_uri(req_uri, pos)?,
+                })
             })
         })
         .collect()
@@ -83,7 +89,7 @@ pub fn get_heading_refs<'a>(
             .find_links()
             .into_iter()
             .filter_map(|link| {
-                let resolved_link = resolve_link(link, state);
+                let resolved_link = resolve_link(link, url, state);
                 match resolved_link {
                     ResolvedLink::InternalHeading { heading, .. }
                     | ResolvedLink::ExternalHeading { heading, .. } => {
@@ -1,42 +1,45 @@
 use std::collections::HashMap;
 
 use lsp_types::{
-    Position, PrepareRenameResponse, Range, RenameParams, TextDocumentPositionParams, TextEdit, Url,
+    PrepareRenameResponse, Range, RenameParams, TextDocumentPositionParams, TextEdit, Url,
 };
 use markdown::mdast::{
     Definition, FootnoteDefinition, FootnoteReference, Heading, LinkReference, Node, ReferenceKind,
-    Text,
 };
 
-use crate::{ast::TraverseNode, links::ResolvedLink, references::get_heading_refs, state::State};
+use crate::{
+    ast::TraverseNode,
+    links::{link_target_offsets, MdLink, ResolvedLink},
+    references::get_heading_refs,
+    slug::{heading_slugs, slug},
+    state::{parse_md, MdFile, State},
+};
 
 pub fn prepare_rename(
     params: &TextDocumentPositionParams,
     state: &State,
 ) -> Option<PrepareRenameResponse> {
     let req_uri = &params.text_document.uri;
-    let req_position = &params.position;
+    let md_file = state.md_files.get(req_uri)?;
+    let offset = md_file.offset(&params.position);
 
-    state
-        .ast_for_uri(req_uri)
-        .and_then(|ast| find_renameable_for_position(ast, req_position))
-        .and_then(|node| prepare_rename_range(node).map(PrepareRenameResponse::Range))
+    find_renameable_for_position(&md_file.ast, offset)
+        .and_then(|node| prepare_rename_range(node, md_file).map(PrepareRenameResponse::Range))
 }
 
 pub fn rename(params: &RenameParams, state: &State) -> Option<HashMap<Url, Vec<TextEdit>>> {
     let req_uri = &params.text_document_position.text_document.uri;
     let new_name = &params.new_name;
-    let req_pos = &params.text_document_position.position;
+    let md_file = state.md_files.get(req_uri)?;
+    let offset = md_file.offset(&params.text_document_position.position);
 
-    let node = state
-        .ast_for_uri(req_uri)
-        .and_then(|ast| find_renameable_for_position(ast, req_pos))?;
+    let node = find_renameable_for_position(&md_file.ast, offset)?;
 
     match node {
         Node::Heading(heading) => {
-            let mut ref_changes = rename_heading_refs(new_name, heading, state);
+            let mut ref_changes = rename_heading_refs(new_name, heading, md_file, state);
             // also rename the heading itself
-            if let Some(range) = heading_rename_range(heading) {
+            if let Some(range) = heading_rename_range(heading, md_file) {
                 let heading_change = TextEdit {
                     range,
                     new_text: new_name.to_string(),
@@ -58,7 +61,7 @@ pub fn rename(params: &RenameParams, state: &State) -> Option<HashMap<Url, Vec<T
         Node::Definition(definition) => {
             rename_link_refs(new_name, req_uri, &definition.identifier, state).map(
                 |mut link_ref_changes| {
-                    if let Some(range) = definition_rename_range(definition) {
+                    if let Some(range) = definition_rename_range(definition, md_file) {
                         let definition_change = TextEdit {
                             range,
                             new_text: new_name.to_string(),
@@ -83,7 +86,7 @@ pub fn rename(params: &RenameParams, state: &State) -> Option<HashMap<Url, Vec<T
         Node::FootnoteDefinition(footnote_def) => {
             rename_footnote_refs(new_name, req_uri, &footnote_def.identifier, state).map(
                 |mut footnote_ref_changes| {
-                    if let Some(range) = footnote_def_rename_range(footnote_def) {
+                    if let Some(range) = footnote_def_rename_range(footnote_def, md_file) {
                         let footnote_def_change = TextEdit {
                             range,
                             new_text: new_name.to_string(),
@@ -101,181 +104,99 @@ pub fn rename(params: &RenameParams, state: &State) -> Option<HashMap<Url, Vec<T
     }
 }
 
-fn prepare_rename_range(node: &Node) -> Option<Range> {
+fn prepare_rename_range(node: &Node, md_file: &MdFile) -> Option<Range> {
     match node {
-        Node::Heading(heading) => heading_rename_range(heading),
-        Node::LinkReference(link_ref) => link_ref_rename_range(link_ref),
-        Node::Definition(definition) => definition_rename_range(definition),
-        Node::FootnoteReference(footnote_ref) => footnote_ref_rename_range(footnote_ref),
-        Node::FootnoteDefinition(footnote_def) => footnote_def_rename_range(footnote_def),
+        Node::Heading(heading) => heading_rename_range(heading, md_file),
+        Node::LinkReference(link_ref) => link_ref_rename_range(link_ref, md_file),
+        Node::Definition(definition) => definition_rename_range(definition, md_file),
+        Node::FootnoteReference(footnote_ref) => footnote_ref_rename_range(footnote_ref, md_file),
+        Node::FootnoteDefinition(footnote_def) => footnote_def_rename_range(footnote_def, md_file),
         _ => None,
     }
 }
 
-fn heading_rename_range(heading: &Heading) -> Option<Range> {
-    let text = get_text_child(&heading.children)?;
-    text.position.as_ref().map(|pos| {
-        let start_line = pos.start.line - 1;
-        let start_char = pos.start.column - 1;
-        let end_line = pos.end.line - 1;
-        let end_char = pos.end.column;
-        rename_range(start_line, end_line, start_char, end_char)
-    })
+/// Range of the inline content of `heading`, without the `#`s
+fn heading_rename_range(heading: &Heading, md_file: &MdFile) -> Option<Range> {
+    let (start, end) = heading_content_offsets(heading)?;
+    Some(md_file.range_from_offsets(start, end))
 }
 
-fn link_ref_rename_range(link_ref: &LinkReference) -> Option<Range> {
-    let kind = link_ref.reference_kind;
-    let text = get_text_child(&link_ref.children)?;
-    link_ref.position.as_ref().map(|link_ref_pos| {
-        let start_line = link_ref_pos.start.line - 1;
-        let start_char = match kind {
-            ReferenceKind::Full => link_ref_pos.start.column + text.value.len() + 2,
-            _ => link_ref_pos.start.column,
-        };
-        let end_line = link_ref_pos.end.line - 1;
-        let end_char = link_ref_pos.end.column - 2;
-        rename_range(start_line, end_line, start_char, end_char)
-    })
+fn heading_content_offsets(heading: &Heading) -> Option<(usize, usize)> {
+    let positions = heading.children.iter().filter_map(|child| child.position());
+    let start = positions.clone().map(|pos| pos.start.offset).min()?;
+    let end = positions.map(|pos| pos.end.offset).max()?;
+    Some((start, end))
 }
 
-fn definition_rename_range(def: &Definition) -> Option<Range> {
-    def.position.as_ref().map(|def_pos| {
-        let start_line = def_pos.start.line - 1;
-        let start_char = def_pos.start.column;
-        let end_line = def_pos.end.line - 1;
-        let end_char = def_pos.start.column + def.identifier.len();
-        rename_range(start_line, end_line, start_char, end_char)
-    })
+/// `[text][label]` or `[label][]` or `[label]`, range of `label`
+fn link_ref_rename_range(link_ref: &LinkReference, md_file: &MdFile) -> Option<Range> {
+    let pos = link_ref.position.as_ref()?;
+    let source = md_file.buffer().get(pos.start.offset..pos.end.offset)?;
+    let (start, end) = match link_ref.reference_kind {
+        ReferenceKind::Full => (source.rfind('[')? + 1, source.len() - 1),
+        _ => (1, source.find(']')?),
+    };
+    Some(md_file.range_from_offsets(pos.start.offset + start, pos.start.offset + end))
 }
 
-fn footnote_ref_rename_range(footnote_ref: &FootnoteReference) -> Option<Range> {
-    footnote_ref.position.as_ref().map(|foot_ref_pos| {
-        let start_line = foot_ref_pos.start.line - 1;
-        let start_char = foot_ref_pos.start.column + 1;
-        let end_line = foot_ref_pos.end.line - 1;
-        let end_char = foot_ref_pos.start.column + footnote_ref.identifier.len() + 1;
-        rename_range(start_line, end_line, start_char, end_char)
-    })
+/// `[label]: url`, range of `label`
+fn definition_rename_range(def: &Definition, md_file: &MdFile) -> Option<Range> {
+    let pos = def.position.as_ref()?;
+    let source = md_file.buffer().get(pos.start.offset..pos.end.offset)?;
+    let end = source.find("]:")?;
+    Some(md_file.range_from_offsets(pos.start.offset + 1, pos.start.offset + end))
 }
 
-fn footnote_def_rename_range(footnote_def: &FootnoteDefinition) -> Option<Range> {
-    footnote_def.position.as_ref().map(|foot_def_pos| {
-        let start_line = foot_def_pos.start.line - 1;
-        let start_char = foot_def_pos.start.column + 1;
-        let end_line = foot_def_pos.start.line - 1;
-        let end_char = foot_def_pos.start.column + footnote_def.identifier.len() + 1;
-        rename_range(start_line, end_line, start_char, end_char)
-    })
+/// `[^label]`, range of `label`
+fn footnote_ref_rename_range(footnote_ref: &FootnoteReference, md_file: &MdFile) -> Option<Range> {
+    let pos = footnote_ref.position.as_ref()?;
+    Some(md_file.range_from_offsets(pos.start.offset + 2, pos.end.offset - 1))
 }
 
-fn find_renameable_for_position<'a>(node: &'a Node, req_pos: &Position) -> Option<&'a Node> {
+/// `[^label]: text`, range of `label`
+fn footnote_def_rename_range(footnote_def: &FootnoteDefinition, md_file: &MdFile) -> Option<Range> {
+    let pos = footnote_def.position.as_ref()?;
+    let source = md_file.buffer().get(pos.start.offset..pos.end.offset)?;
+    let end = source.find("]:")?;
+    Some(md_file.range_from_offsets(pos.start.offset + 2, pos.start.offset + end))
+}
+
+fn find_renameable_for_position(node: &Node, offset: usize) -> Option<&Node> {
     node.ast_iter().find(|node| match node {
         Node::Heading(Heading { position, .. })
         | Node::LinkReference(LinkReference { position, .. })
         | Node::Definition(Definition { position, .. })
-        | Node::FootnoteReference(FootnoteReference { position, .. }) => {
+        | Node::FootnoteReference(FootnoteReference { position, .. })
+        | Node::FootnoteDefinition(FootnoteDefinition { position, .. }) => {
             if let Some(pos) = position {
-                (req_pos.line + 1) as usize >= pos.start.line
-                    && (req_pos.line + 1) as usize <= pos.end.line
-                    && (req_pos.character + 1) as usize >= pos.start.column
-                    && (req_pos.character + 1) as usize <= pos.end.column
+                offset >= pos.start.offset && offset <= pos.end.offset
             } else {
                 false
             }
         }
-        Node::FootnoteDefinition(FootnoteDefinition {
-            position: Some(pos),
-            ..
-        }) => {
-            (req_pos.line + 1) as usize >= pos.start.line
-                && (req_pos.line + 1) as usize <= pos.end.line
-                && (req_pos.character + 1) as usize >= pos.start.column
-            //&& (req_pos.character + 1) as usize <= pos.end.column
-        }
         _ => false,
     })
 }
 
-// fn find_renameable_for_position<'a>(node: &'a Node, req_pos: &Position) -> Option<&'a Node> {
-//     match node {
-//         Node::Heading(Heading { position, .. })
-//         | Node::LinkReference(LinkReference { position, .. })
-//         | Node::Definition(Definition { position, .. })
-//         | Node::FootnoteReference(FootnoteReference { position, .. }) => {
-//             if let Some(pos) = position {
-//                 if (req_pos.line + 1) as usize >= pos.start.line
-//                     && (req_pos.line + 1) as usize <= pos.end.line
-//                     && (req_pos.character + 1) as usize >= pos.start.column
-//                     && (req_pos.character + 1) as usize <= pos.end.column
-//                 {
-//                     return Some(node);
-//                 }
-//             };
-//         }
-//         Node::FootnoteDefinition(FootnoteDefinition {
-//             position: Some(pos),
-//             ..
-//         }) => {
-//             if (req_pos.line + 1) as usize >= pos.start.line
-//                 && (req_pos.line + 1) as usize <= pos.end.line
-//                 && (req_pos.character + 1) as usize >= pos.start.column
-//             //&& (req_pos.character + 1) as usize <= pos.end.column
-//             {
-//                 return Some(node);
-//             }
-//         }
-//         _ => {}
-//     }
-//
-//     traverse_ast!(node, find_renameable_for_position, req_pos)
-// }
-
-pub fn get_text_child(children: &Vec<Node>) -> Option<&Text> {
-    for child in children {
-        if let Node::Text(t) = child {
-            return Some(t);
-        }
-    }
-    None
-}
-
-fn rename_range(start_line: usize, end_line: usize, start_char: usize, end_char: usize) -> Range {
-    let start = Position {
-        line: start_line as u32,
-        character: start_char as u32,
-    };
-    let end = Position {
-        line: end_line as u32,
-        character: end_char as u32,
-    };
-    Range { start, end }
-}
-
 /// Renaming of references to headings, these are contained in links
 fn rename_heading_refs(
     new_name: &str,
     heading: &Heading,
+    md_file: &MdFile,
     state: &State,
 ) -> HashMap<Url, Vec<TextEdit>> {
+    let new_slug = renamed_heading_slug(new_name, heading, md_file);
     get_heading_refs(heading, state).into_iter().fold(
         HashMap::new(),
         |mut acc, (link_uri, found_ref)| match found_ref {
             ResolvedLink::InternalHeading { ref link, .. }
             | ResolvedLink::ExternalHeading { ref link, .. } => {
-                if let (Some(pos), Some(heading_text)) = (link.position(), found_ref.heading_text())
-                {
-                    let start_line = pos.start.line - 1;
-                    let mut start_char = pos.end.column - 2 - heading_text.len();
-                    let end_line = pos.end.line - 1;
-                    let mut end_char = pos.end.column - 2;
-                    let mut new_text = new_name.to_string();
-                    if link.is_wikilink() {
-                        start_char -= 1;
-                        end_char -= 1;
+                if let Some(range) = heading_ref_range(link, state.md_files.get(link_uri)) {
+                    let new_text = if link.is_wikilink() {
+                        new_name.to_string()
                     } else {
-                        new_text = new_text.to_lowercase().replace(' ', "-");
-                    }
-                    let range = rename_range(start_line, end_line, start_char, end_char);
+                        new_slug.clone()
+                    };
                     let text_edit = TextEdit { range, new_text };
                     acc.entry(link_uri.clone()).or_default().push(text_edit);
                 }
@@ -286,6 +207,34 @@ fn rename_heading_refs(
     )
 }
 
+/// Slug of `heading` after renaming it to `new_name`, with the suffix of a
+/// duplicate heading (`setup-1`)
+fn renamed_heading_slug(new_name: &str, heading: &Heading, md_file: &MdFile) -> String {
+    let renamed_slug = || {
+        let index = md_file
+            .ast
+            .find_headings()
+            .iter()
+            .position(|other| other.position == heading.position)?;
+        let (start, end) = heading_content_offsets(heading)?;
+        let mut buffer = md_file.buffer().to_string();
+        buffer.replace_range(start..end, new_name);
+        let ast = parse_md(&buffer);
+        let (_, slug) = heading_slugs(&ast).into_iter().nth(index)?;
+        Some(slug)
+    };
+    renamed_slug().unwrap_or_else(|| slug(new_name))
+}
+
+/// Range of the heading part after `#` in the target of a link
+fn heading_ref_range(link: &MdLink, md_file: Option<&MdFile>) -> Option<Range> {
+    let md_file = md_file?;
+    let (start, end) = link_target_offsets(link, md_file.buffer())?;
+    let target = md_file.buffer().get(start..end)?;
+    let fragment_start = start + target.find('#')? + 1;
+    Some(md_file.range_from_offsets(fragment_start, end))
+}
+
 /// There is always one definition, which is in same file as the request, I asume
 fn rename_definition(
     new_name: &str,
@@ -294,9 +243,12 @@ fn rename_definition(
     state: &State,
 ) -> HashMap<Url, Vec<TextEdit>> {
     let mut definition_changes = HashMap::new();
-    if let Some(ast) = state.ast_for_uri(req_uri) {
-        if let Some(definition) = ast.find_definition_for_identifier(&link_ref.identifier) {
-            if let Some(range) = definition_rename_range(definition) {
+    if let Some(md_file) = state.md_files.get(req_uri) {
+        if let Some(definition) = md_file
+            .ast
+            .find_definition_for_identifier(&link_ref.identifier)
+        {
+            if let Some(range) = definition_rename_range(definition, md_file) {
                 let text_edit = TextEdit {
                     range,
                     new_text: new_name.to_string(),
@@ -317,12 +269,12 @@ fn rename_link_refs(
     identifier: &str,
     state: &State,
 ) -> Option<HashMap<Url, Vec<TextEdit>>> {
-    let ast = state.ast_for_uri(req_uri)?;
-    let link_refs = ast.find_link_references_for_identifier(identifier);
+    let md_file = state.md_files.get(req_uri)?;
+    let link_refs = md_file.ast.find_link_references_for_identifier(identifier);
     let link_refs = link_refs.into_iter().fold(
         HashMap::new(),
         |mut acc: HashMap<Url, Vec<TextEdit>>, link_ref| {
-            if let Some(range) = link_ref_rename_range(link_ref) {
+            if let Some(range) = link_ref_rename_range(link_ref, md_file) {
                 let text_edit = TextEdit {
                     range,
                     new_text: new_name.to_string(),
@@ -342,11 +294,12 @@ fn rename_footnote_def(
     state: &State,
 ) -> HashMap<Url, Vec<TextEdit>> {
     let mut footnote_def_changes = HashMap::new();
-    if let Some(ast) = state.ast_for_uri(req_uri) {
-        if let Some(footnote_def) =
-            ast.find_foot_definition_for_identifier(&footnote_ref.identifier)
+    if let Some(md_file) = state.md_files.get(req_uri) {
+        if let Some(footnote_def) = md_file
+            .ast
+            .find_foot_definition_for_identifier(&footnote_ref.identifier)
         {
-            if let Some(range) = footnote_def_rename_range(footnote_def) {
+            if let Some(range) = footnote_def_rename_range(footnote_def, md_file) {
                 let text_edit = TextEdit {
                     range,
                     new_text: new_name.to_string(),
@@ -366,12 +319,14 @@ fn rename_footnote_refs(
     identifier: &str,
     state: &State,
 ) -> Option<HashMap<Url, Vec<TextEdit>>> {
-    let ast = state.ast_for_uri(req_uri)?;
-    let footnote_refs = ast.find_footnote_references_for_identifier(identifier);
+    let md_file = state.md_files.get(req_uri)?;
+    let footnote_refs = md_file
+        .ast
+        .find_footnote_references_for_identifier(identifier);
     let footnote_refs = footnote_refs.into_iter().fold(
         HashMap::new(),
         |mut acc: HashMap<Url, Vec<TextEdit>>, footnote_ref| {
-            if let Some(range) = footnote_ref_rename_range(footnote_ref) {
+            if let Some(range) = footnote_ref_rename_range(footnote_ref, md_file) {
                 let text_edit = TextEdit {
                     range,
                     new_text: new_name.to_string(),
@@ -393,3 +348,43 @@ where
         map1.entry(key).or_insert_with(|| Vec::new()).extend(values);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, TextDocumentIdentifier, WorkDoneProgressParams, WorkspaceFolder};
+
+    use super::*;
+
+    #[test]
+    fn test_rename_duplicate_heading() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        let uri = Url::from_file_path(root.join("index.md")).unwrap();
+        let other_uri = Url::from_file_path(root.join("other.md")).unwrap();
+        state.set_buffer(
+            &uri,
+            "# Setup\n\n# Install\n\n# Setup\n\n[second](#setup-1)\n".to_string(),
+        );
+        state.set_buffer(&other_uri, "[second](index.md#setup-1)\n".to_string());
+        // renaming the second `Setup` to `Install` makes it the second `Install`
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position::new(4, 3),
+            },
+            new_name: "Install".to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let changes = rename(&params, &state).unwrap();
+        let new_texts: Vec<&str> = changes[&uri]
+            .iter()
+            .map(|edit| edit.new_text.as_str())
+            .collect();
+        assert_eq!(new_texts, vec!["install-1", "Install"]);
+        assert_eq!(changes[&other_uri][0].new_text, "install-1");
+    }
+}
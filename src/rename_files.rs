@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use lsp_types::{RenameFilesParams, TextEdit, Url};
+use markdown::mdast::Link;
+
+use crate::{
+    ast::TraverseNode,
+    links::{
+        definition_target_offsets, image_target_offsets, link_target_offsets, relative_link_path,
+        resolve_asset, resolve_link, shortest_wikilink_target_in, url_encode, MdLink, ResolvedLink,
+    },
+    state::{path_from_root, MdFile, State},
+};
+
+/// Updates all links to renamed files and the relative links of the renamed
+/// files. Renamed directories rename all files under them.
+pub fn will_rename_files(
+    params: &RenameFilesParams,
+    state: &State,
+) -> Option<HashMap<Url, Vec<TextEdit>>> {
+    let renames = renamed_paths(params, state);
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for (link_uri, text_edit) in link_changes(&renames, state) {
+        changes.entry(link_uri).or_default().push(text_edit);
+    }
+    Some(changes)
+}
+
+/// Old and new paths of all renamed files, including the files under renamed
+/// directories
+fn renamed_paths(params: &RenameFilesParams, state: &State) -> HashMap<PathBuf, PathBuf> {
+    let file_paths: Vec<PathBuf> = state
+        .md_files
+        .keys()
+        .chain(state.asset_files())
+        .filter_map(|uri| uri.to_file_path().ok())
+        .collect();
+    let mut renames = HashMap::new();
+    for file_rename in &params.files {
+        let (Some(old_path), Some(new_path)) = (
+            file_path(&file_rename.old_uri),
+            file_path(&file_rename.new_uri),
+        ) else {
+            continue;
+        };
+        for path in &file_paths {
+            if let Ok(relative) = path.strip_prefix(&old_path) {
+                renames.insert(path.clone(), new_path.join(relative));
+            }
+        }
+    }
+    renames
+}
+
+fn file_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+/// At most one edit per link, image or definition whose file or target is
+/// renamed
+fn link_changes(renames: &HashMap<PathBuf, PathBuf>, state: &State) -> Vec<(Url, TextEdit)> {
+    let renamed = |path: PathBuf| renames.get(&path).cloned().unwrap_or(path);
+    // wikilinks by file name have to stay unambiguous between the renamed files
+    let file_paths: Vec<PathBuf> = state
+        .md_files
+        .keys()
+        .chain(state.asset_files())
+        .filter_map(|uri| uri.to_file_path().ok())
+        .map(renamed)
+        .collect();
+    let mut changes = Vec::new();
+    for (uri, md_file) in state.md_files.iter() {
+        let Ok(path) = uri.to_file_path() else {
+            continue;
+        };
+        let old_dir = path.parent().map(Path::to_path_buf);
+        let old_dir = old_dir.as_deref();
+        // relative links of the renamed file start from its new directory
+        let link_path = renamed(path.clone());
+        let Some(link_dir) = link_path.parent() else {
+            continue;
+        };
+        for file_ref in file_refs(uri, md_file, state) {
+            let old_target_path = &file_ref.target;
+            let is_renamed_target = renames.contains_key(old_target_path);
+            let target_path = renamed(old_target_path.clone());
+            let is_root_relative = file_ref.url.starts_with('/');
+            // `[[page]]` resolves by file name, independent of the directory
+            let is_basename_wikilink = file_ref.is_wikilink
+                && !file_ref
+                    .url
+                    .split('#')
+                    .next()
+                    .unwrap_or_default()
+                    .contains('/');
+            let needs_update = if is_basename_wikilink {
+                is_renamed_target && target_path.file_name() != old_target_path.file_name()
+            } else if is_root_relative {
+                is_renamed_target
+            } else {
+                // files moved together keep their relative links
+                old_dir.is_some_and(|old_dir| {
+                    relative_link_path(old_dir, old_target_path)
+                        != relative_link_path(link_dir, &target_path)
+                })
+            };
+            if !needs_update {
+                continue;
+            }
+            let new_file_ref = if is_basename_wikilink {
+                match state.workspace_root_for_uri(uri).and_then(|root| {
+                    shortest_wikilink_target_in(&target_path, &root, &file_paths, state)
+                }) {
+                    Some(target) => target,
+                    None => continue,
+                }
+            } else if is_root_relative {
+                match state
+                    .workspace_root_for_uri(uri)
+                    .and_then(|root| path_from_root(&root, &target_path))
+                {
+                    Some(path) => path,
+                    None => continue,
+                }
+            } else {
+                relative_link_path(link_dir, &target_path)
+            };
+            let (start, end) = file_ref.offsets;
+            let new_text = new_link_target(&file_ref, &new_file_ref, state);
+            if md_file.buffer().get(start..end) == Some(new_text.as_str()) {
+                continue;
+            }
+            changes.push((
+                uri.clone(),
+                TextEdit {
+                    range: md_file.range_from_offsets(start, end),
+                    new_text,
+                },
+            ));
+        }
+    }
+    changes
+}
+
+/// Target of a link, image or definition that points to a file of the
+/// workspace
+struct FileRef<'a> {
+    /// The target as in the AST, with heading
+    url: &'a str,
+    is_wikilink: bool,
+    /// Byte offsets of the target in the buffer
+    offsets: (usize, usize),
+    /// Path of the file it resolves to
+    target: PathBuf,
+}
+
+/// Links, images and definitions of `md_file` that resolve to a file
+fn file_refs<'a>(uri: &Url, md_file: &'a MdFile, state: &State) -> Vec<FileRef<'a>> {
+    let buffer = md_file.buffer();
+    let resolved_file = |link: &Link| match resolve_link(link, uri, state) {
+        ResolvedLink::File { link, file_uri }
+        | ResolvedLink::ExternalHeading { link, file_uri, .. }
+        | ResolvedLink::Asset { link, file_uri } => {
+            Some((link.is_wikilink(), file_uri.to_file_path().ok()?))
+        }
+        _ => None,
+    };
+    let links = md_file.ast.find_links().into_iter().filter_map(|link| {
+        let (is_wikilink, target) = resolved_file(link)?;
+        Some(FileRef {
+            url: &link.url,
+            is_wikilink,
+            offsets: link_target_offsets(&MdLink::new(link), buffer)?,
+            target,
+        })
+    });
+    let images = md_file.ast.find_images().into_iter().filter_map(|image| {
+        Some(FileRef {
+            url: &image.url,
+            is_wikilink: false,
+            offsets: image_target_offsets(image, buffer)?,
+            target: resolve_asset(&image.url, uri, state)?.to_file_path().ok()?,
+        })
+    });
+    // definitions resolve like the links using them
+    let definitions = md_file.ast.find_defintions().into_iter().filter_map(|def| {
+        let link = Link {
+            children: Vec::new(),
+            position: def.position.clone(),
+            url: def.url.clone(),
+            title: None,
+        };
+        let (_, target) = resolved_file(&link)?;
+        Some(FileRef {
+            url: &def.url,
+            is_wikilink: false,
+            offsets: definition_target_offsets(def, buffer)?,
+            target,
+        })
+    });
+    links.chain(images).chain(definitions).collect()
+}
+
+/// New target for `file_ref`, keeps the heading and the style (with or without
+/// markdown file extension) of the old target
+fn new_link_target(file_ref: &FileRef, new_path: &str, state: &State) -> String {
+    let (old_file, heading) = match file_ref.url.split_once('#') {
+        Some((file, heading)) => (file, Some(heading)),
+        None => (file_ref.url, None),
+    };
+    let mut target = if state.is_md_file(Path::new(old_file)) {
+        new_path.to_string()
+    } else {
+        state.settings().strip_md_extension(new_path).to_string()
+    };
+    if !file_ref.is_wikilink {
+        target = url_encode(&target);
+    }
+    match heading {
+        Some(heading) => format!("{target}#{heading}"),
+        None => target,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{FileRename, WorkspaceFolder};
+
+    use super::*;
+
+    #[test]
+    fn test_will_rename_files() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        let old_uri = Url::from_file_path(root.join("notes/a.md")).unwrap();
+        let new_uri = Url::from_file_path(root.join("archive/old/a b.md")).unwrap();
+        let linking_uri = Url::from_file_path(root.join("index.md")).unwrap();
+        state.set_buffer(
+            &old_uri,
+            "# A\n\n## Section\n\n[index](../index.md) [[/index]]\n".to_string(),
+        );
+        state.set_buffer(
+            &linking_uri,
+            "[a](/notes/a.md#section) [[/notes/a]] [[/notes/a#Section]] [[#Other]] [[a|A]]\n\n\
+            # Other\n\n[relative](notes/a.md) [relative](./notes/a)\n"
+                .to_string(),
+        );
+        let params = RenameFilesParams {
+            files: vec![FileRename {
+                old_uri: old_uri.to_string(),
+                new_uri: new_uri.to_string(),
+            }],
+        };
+        let changes = will_rename_files(&params, &state).unwrap();
+        let mut new_texts: Vec<&str> = changes[&linking_uri]
+            .iter()
+            .map(|edit| edit.new_text.as_str())
+            .collect();
+        new_texts.sort();
+        assert_eq!(
+            new_texts,
+            vec![
+                "/archive/old/a b",
+                "/archive/old/a b#Section",
+                "/archive/old/a%20b.md#section",
+                "a b",
+                "archive/old/a%20b",
+                "archive/old/a%20b.md",
+            ]
+        );
+        let renamed_file_changes: Vec<&str> = changes[&old_uri]
+            .iter()
+            .map(|edit| edit.new_text.as_str())
+            .collect();
+        assert_eq!(renamed_file_changes, vec!["../../index.md"]);
+    }
+
+    #[test]
+    fn test_rename_files_linking_to_each_other() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        let uri = |path: &str| Url::from_file_path(root.join(path)).unwrap();
+        state.set_buffer(
+            &uri("notes/a.md"),
+            "[b](b.md) [[b]] [index](../index.md)\n".to_string(),
+        );
+        state.set_buffer(
+            &uri("notes/b.md"),
+            "[a](./a.md#a) [[/notes/a]]\n".to_string(),
+        );
+        state.set_buffer(&uri("notes/c.md"), "[a](a.md) [[a]]\n".to_string());
+        state.set_buffer(&uri("other/b.md"), "# Other b\n".to_string());
+        state.set_buffer(&uri("index.md"), "[c](notes/c.md) [[b]]\n".to_string());
+        let params = RenameFilesParams {
+            files: vec![
+                FileRename {
+                    old_uri: uri("notes/a.md").to_string(),
+                    new_uri: uri("archive/a.md").to_string(),
+                },
+                FileRename {
+                    old_uri: uri("notes/b.md").to_string(),
+                    new_uri: uri("archive/sub/b.md").to_string(),
+                },
+            ],
+        };
+        let changes = will_rename_files(&params, &state).unwrap();
+        let new_texts = |path: &str| -> Vec<String> {
+            let mut new_texts: Vec<String> = changes
+                .get(&uri(path))
+                .into_iter()
+                .flatten()
+                .map(|edit| edit.new_text.clone())
+                .collect();
+            new_texts.sort();
+            new_texts
+        };
+        // `[[b]]` resolves by file name wherever `b.md` is
+        assert_eq!(new_texts("notes/a.md"), vec!["sub/b.md"]);
+        assert_eq!(new_texts("notes/b.md"), vec!["../a.md#a", "/archive/a"]);
+        assert_eq!(new_texts("notes/c.md"), vec!["../archive/a.md"]);
+        assert!(new_texts("index.md").is_empty());
+        let edits = changes.values().map(Vec::len).sum::<usize>();
+        assert_eq!(edits, 4);
+
+        // a renamed directory renames the files under it
+        let params = RenameFilesParams {
+            files: vec![FileRename {
+                old_uri: uri("notes").to_string(),
+                new_uri: uri("docs/notes").to_string(),
+            }],
+        };
+        let changes = will_rename_files(&params, &state).unwrap();
+        assert_eq!(changes[&uri("index.md")][0].new_text, "docs/notes/c.md");
+        assert_eq!(changes[&uri("notes/a.md")][0].new_text, "../../index.md");
+        assert_eq!(changes[&uri("notes/b.md")][0].new_text, "/docs/notes/a");
+        assert_eq!(changes.values().map(Vec::len).sum::<usize>(), 3);
+
+        // `[[b]]` would be ambiguous
+        let params = RenameFilesParams {
+            files: vec![FileRename {
+                old_uri: uri("notes/a.md").to_string(),
+                new_uri: uri("archive/b.md").to_string(),
+            }],
+        };
+        let changes = will_rename_files(&params, &state).unwrap();
+        let new_texts: Vec<&str> = changes[&uri("notes/c.md")]
+            .iter()
+            .map(|edit| edit.new_text.as_str())
+            .collect();
+        assert_eq!(new_texts, vec!["../archive/b.md", "archive/b"]);
+    }
+
+    #[test]
+    fn test_rename_definitions_and_assets() {
+        let root = std::env::current_dir().unwrap();
+        let mut state = State::new();
+        state.add_workspace_folder(WorkspaceFolder {
+            uri: Url::from_file_path(&root).unwrap(),
+            name: "root".to_string(),
+        });
+        let uri = |path: &str| Url::from_file_path(root.join(path)).unwrap();
+        // existing non-markdown files of the repository are the assets
+        state.load_file(&uri("LICENSE"));
+        state.load_file(&uri("Cargo.toml"));
+        state.set_buffer(
+            &uri("notes/a.md"),
+            "[x][ref]\n\n![license](../LICENSE \"License\")\n\n[ref]: b.md#top\n".to_string(),
+        );
+        state.set_buffer(&uri("notes/b.md"), "# Top\n".to_string());
+        state.set_buffer(
+            &uri("index.md"),
+            "[a][]\n\n[a]: <notes/a.md>\n\n[manifest](Cargo.toml)\n".to_string(),
+        );
+        let rename = |old: &str, new: &str| {
+            let params = RenameFilesParams {
+                files: vec![FileRename {
+                    old_uri: uri(old).to_string(),
+                    new_uri: uri(new).to_string(),
+                }],
+            };
+            let changes = will_rename_files(&params, &state).unwrap();
+            let mut new_texts: Vec<(String, String)> = changes
+                .into_iter()
+                .flat_map(|(uri, edits)| {
+                    let name = uri.path().rsplit('/').next().unwrap().to_string();
+                    edits
+                        .into_iter()
+                        .map(move |edit| (name.clone(), edit.new_text))
+                })
+                .collect();
+            new_texts.sort();
+            new_texts
+        };
+        let owned = |new_texts: &[(&str, &str)]| -> Vec<(String, String)> {
+            new_texts
+                .iter()
+                .map(|(name, text)| (name.to_string(), text.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            rename("notes/a.md", "archive/old/a.md"),
+            owned(&[
+                ("a.md", "../../LICENSE"),
+                ("a.md", "../../notes/b.md#top"),
+                ("index.md", "archive/old/a.md"),
+            ])
+        );
+        assert_eq!(
+            rename("Cargo.toml", "config/Cargo.toml"),
+            owned(&[("index.md", "config/Cargo.toml")])
+        );
+    }
+}
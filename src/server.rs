@@ -1,41 +1,135 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+
 use anyhow::Result;
 use lsp_server::{Connection, Message, Notification, RequestId, Response};
 use lsp_types::notification::{
-    DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument, DidOpenTextDocument, Exit,
-    Notification as _,
+    DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
+    DidChangeWorkspaceFolders, DidCloseTextDocument, DidOpenTextDocument, Exit, Notification as _,
 };
 use lsp_types::request::{
-    CodeActionRequest, Completion, DocumentDiagnosticRequest, DocumentSymbolRequest, Formatting,
-    GotoDefinition, HoverRequest, PrepareRenameRequest, RangeFormatting, References, Rename,
-    Request, Shutdown, WorkspaceSymbolRequest,
+    CodeActionRequest, CodeActionResolveRequest, Completion, DocumentDiagnosticRequest,
+    DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, OnTypeFormatting,
+    PrepareRenameRequest, RangeFormatting, References, RegisterCapability, Rename, Request,
+    Shutdown, WillRenameFiles, WorkspaceConfiguration, WorkspaceDiagnosticRefresh,
+    WorkspaceDiagnosticRequest, WorkspaceSymbolRequest,
 };
 use lsp_types::{
-    CodeActionParams, CompletionParams, CompletionResponse, Diagnostic, DiagnosticSeverity,
-    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-    DocumentFormattingParams, DocumentRangeFormattingParams, DocumentSymbolParams,
-    GotoDefinitionParams, HoverParams, NumberOrString, PublishDiagnosticsParams, ReferenceParams,
-    RenameParams, TextDocumentPositionParams, Url, WorkspaceEdit,
+    CodeAction, CodeActionParams, CompletionParams, CompletionResponse, ConfigurationItem,
+    ConfigurationParams, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+    DidChangeWatchedFilesParams, DidChangeWatchedFilesRegistrationOptions,
+    DidChangeWorkspaceFoldersParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentDiagnosticParams, DocumentFormattingParams, DocumentOnTypeFormattingParams,
+    DocumentRangeFormattingParams, DocumentSymbolParams, FileChangeType, FileSystemWatcher,
+    GlobPattern, GotoDefinitionParams, HoverParams, PublishDiagnosticsParams, ReferenceParams,
+    Registration, RegistrationParams, RenameFilesParams, RenameParams, TextDocumentPositionParams,
+    Url, WorkspaceDiagnosticParams, WorkspaceEdit,
 };
 use serde::Serialize;
+use serde_json::Value;
 
-use crate::code_actions::code_actions;
+use crate::code_actions::{code_actions, resolve_code_action};
 use crate::completion::completion;
 use crate::definition::definition;
-use crate::diagnostics::check_links;
-use crate::formatting::{formatting, range_formatting};
+use crate::diagnostics::{diagnostics, document_diagnostic, workspace_diagnostic};
+use crate::formatting::{formatting, on_type_formatting, range_formatting};
 use crate::hover::hover;
 use crate::references::references;
 use crate::rename::{prepare_rename, rename};
+use crate::rename_files::will_rename_files;
+use crate::settings::{PROJECT_FILE, SECTION};
 use crate::state::State;
 use crate::symbols::{document_symbols, workspace_symbols};
 
 pub struct Server {
     connection: Connection,
+    /// Id for the next request sent to the client
+    next_request_id: Cell<i32>,
+    /// The client pulls diagnostics, so they are not published
+    pull_diagnostics: bool,
+    /// The client supports `workspace/diagnostic/refresh`
+    diagnostic_refresh: bool,
+    /// The client supports `workspace/configuration`
+    workspace_configuration: bool,
+    /// Id of the pending `workspace/configuration` request
+    configuration_request: Cell<Option<RequestId>>,
 }
 
 impl Server {
     pub fn new(connection: Connection) -> Self {
-        Self { connection }
+        Self {
+            connection,
+            next_request_id: Cell::new(0),
+            pull_diagnostics: false,
+            diagnostic_refresh: false,
+            workspace_configuration: false,
+            configuration_request: Cell::new(None),
+        }
+    }
+
+    /// Clients supporting `workspace/configuration` are asked for the
+    /// settings, instead of relying on `workspace/didChangeConfiguration`
+    pub fn enable_workspace_configuration(&mut self) {
+        self.workspace_configuration = true;
+    }
+
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_configuration
+    pub fn request_configuration(&self) -> Result<()> {
+        if !self.workspace_configuration {
+            return Ok(());
+        }
+        let id = self.send_request::<WorkspaceConfiguration>(ConfigurationParams {
+            items: vec![ConfigurationItem {
+                scope_uri: None,
+                section: Some(SECTION.to_string()),
+            }],
+        })?;
+        self.configuration_request.set(Some(id));
+        Ok(())
+    }
+
+    /// Clients supporting pull diagnostics request them, instead of getting
+    /// them published
+    pub fn enable_pull_diagnostics(&mut self, refresh_support: bool) {
+        self.pull_diagnostics = true;
+        self.diagnostic_refresh = refresh_support;
+    }
+
+    /// Asks clients using pull diagnostics to pull again, e.g. after files
+    /// changed on disk
+    fn refresh_diagnostics(&self) -> Result<()> {
+        if self.pull_diagnostics && self.diagnostic_refresh {
+            self.send_request::<WorkspaceDiagnosticRefresh>(())?;
+        }
+        Ok(())
+    }
+
+    fn send_request<R: Request>(&self, params: R::Params) -> Result<RequestId> {
+        let id = RequestId::from(self.next_request_id.get());
+        self.next_request_id.set(self.next_request_id.get() + 1);
+        let request = lsp_server::Request::new(id.clone(), R::METHOD.to_string(), params);
+        self.connection.sender.send(Message::Request(request))?;
+        Ok(id)
+    }
+
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#client_registerCapability
+    pub fn register_file_watchers(&self) -> Result<()> {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                // all files, links to non-markdown files are checked too
+                glob_pattern: GlobPattern::String("**/*".to_string()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "md-lsp-watched-files".to_string(),
+            method: DidChangeWatchedFiles::METHOD.to_string(),
+            register_options: Some(serde_json::to_value(options)?),
+        };
+        self.send_request::<RegisterCapability>(RegistrationParams {
+            registrations: vec![registration],
+        })?;
+        Ok(())
     }
 
     fn send_result<S: Serialize>(&self, req_id: RequestId, result: S) -> Result<()> {
@@ -51,6 +145,8 @@ impl Server {
                     if self.connection.handle_shutdown(&req)? {
                         return Ok(());
                     }
+                    // requests must see the latest state of all buffers
+                    self.flush_changes(&mut state)?;
                     match req.method.as_ref() {
                         GotoDefinition::METHOD => self.handle_defintion(req, &mut state)?,
                         HoverRequest::METHOD => self.handle_hover(req, &mut state)?,
@@ -63,12 +159,22 @@ impl Server {
                         }
                         Formatting::METHOD => self.handle_formatting(req, &mut state)?,
                         RangeFormatting::METHOD => self.handle_range_formatting(req, &mut state)?,
+                        OnTypeFormatting::METHOD => {
+                            self.handle_on_type_formatting(req, &mut state)?
+                        }
                         PrepareRenameRequest::METHOD => self.handle_prepare_rename(req, &state)?,
                         Rename::METHOD => self.handle_rename(req, &state)?,
+                        WillRenameFiles::METHOD => self.handle_will_rename_files(req, &state)?,
                         DocumentDiagnosticRequest::METHOD => {
-                            log::info!("DIAGNOSTIC REQUEST: {:?}", req);
+                            self.handle_document_diagnostic(req, &state)?
+                        }
+                        WorkspaceDiagnosticRequest::METHOD => {
+                            self.handle_workspace_diagnostic(req, &state)?
                         }
                         CodeActionRequest::METHOD => self.handle_code_action(req, &state)?,
+                        CodeActionResolveRequest::METHOD => {
+                            self.handle_code_action_resolve(req, &state)?
+                        }
                         Completion::METHOD => self.handle_completion(req, &state)?,
                         Shutdown::METHOD => self.handle_shutdown(req)?,
                         _ => {
@@ -78,22 +184,76 @@ impl Server {
                 }
                 Message::Response(resp) => {
                     log::info!("GOT RESPONSE: {resp:?}");
+                    self.handle_response(resp, &mut state)?;
                 }
                 Message::Notification(not) => match not.method.as_ref() {
                     DidOpenTextDocument::METHOD => self.handle_did_open(not, &mut state)?,
                     DidChangeTextDocument::METHOD => self.handle_did_change(not, &mut state)?,
-                    DidCloseTextDocument::METHOD => self.handle_did_close(not)?,
-                    DidChangeWatchedFiles::METHOD => self.handle_did_change_watched_files(not)?,
+                    DidCloseTextDocument::METHOD => self.handle_did_close(not, &mut state)?,
+                    DidChangeWatchedFiles::METHOD => {
+                        self.handle_did_change_watched_files(not, &mut state)?
+                    }
+                    DidChangeWorkspaceFolders::METHOD => {
+                        self.handle_did_change_workspace_folders(not, &mut state)?
+                    }
+                    DidChangeConfiguration::METHOD => {
+                        self.handle_did_change_configuration(not, &mut state)?
+                    }
                     Exit::METHOD => self.handle_exit(not),
                     _ => {
                         log::info!("OTHER NOTIFICATION: {:?}", not)
                     }
                 },
             }
+            // debounce: only re-parse when there are no more queued messages
+            if self.connection.receiver.is_empty() {
+                self.flush_changes(&mut state)?;
+            }
         }
         Ok(())
     }
 
+    /// Re-parses changed buffers and publishes their diagnostics
+    fn flush_changes(&self, state: &mut State) -> Result<()> {
+        for uri in state.reparse_dirty() {
+            self.handle_diagnostic(&uri, state)?;
+        }
+        Ok(())
+    }
+
+    /// Responses to requests sent to the client
+    fn handle_response(&self, resp: Response, state: &mut State) -> Result<()> {
+        // responses to other requests, like `client/registerCapability`, may
+        // arrive before the configuration
+        let pending = self.configuration_request.take();
+        if pending.as_ref() != Some(&resp.id) {
+            self.configuration_request.set(pending);
+            return Ok(());
+        }
+        let Some(result) = resp.result else {
+            log::warn!("workspace/configuration failed: {:?}", resp.error);
+            return Ok(());
+        };
+        let settings: Vec<Value> = serde_json::from_value(result)?;
+        let client_settings = settings.into_iter().next().unwrap_or_default();
+        let reindex = state.set_client_settings(client_settings);
+        self.settings_changed(reindex, state)
+    }
+
+    /// Re-indexes the workspace if necessary and updates all diagnostics
+    fn settings_changed(&self, reindex: bool, state: &mut State) -> Result<()> {
+        if reindex {
+            for uri in state.reindex() {
+                self.clear_diagnostics(&uri)?;
+            }
+        }
+        let uris: Vec<Url> = state.md_files.keys().cloned().collect();
+        for uri in uris {
+            self.handle_diagnostic(&uri, state)?;
+        }
+        self.refresh_diagnostics()
+    }
+
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#shutdown
     fn handle_shutdown(&self, req: lsp_server::Request) -> Result<()> {
         log::info!("SHUTDOWN REQ: {:?}", &req);
@@ -116,7 +276,7 @@ impl Server {
     fn handle_did_open(&self, not: lsp_server::Notification, state: &mut State) -> Result<()> {
         let params: DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
         let uri = params.text_document.uri;
-        state.set_buffer(&uri, params.text_document.text);
+        state.open_file(&uri, params.text_document.text);
 
         Ok(())
     }
@@ -124,45 +284,28 @@ impl Server {
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_didChange
     fn handle_did_change(&self, not: lsp_server::Notification, state: &mut State) -> Result<()> {
         let params: DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
-        let change_event = params.content_changes.into_iter().next_back().unwrap();
         let uri = params.text_document.uri;
-        state.set_buffer(&uri, change_event.text);
-        self.handle_diagnostic(&uri, state)?;
+        for change_event in params.content_changes {
+            state.apply_change(&uri, change_event);
+        }
 
         Ok(())
     }
 
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_didClose
-    fn handle_did_close(&self, not: lsp_server::Notification) -> Result<()> {
-        let _params: DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+    fn handle_did_close(&self, not: lsp_server::Notification, state: &mut State) -> Result<()> {
+        let params: DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+        state.close_file(&params.text_document.uri);
 
         Ok(())
     }
 
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_publishDiagnostics
     pub fn handle_diagnostic(&self, req_uri: &Url, state: &State) -> Result<()> {
-        let ast = state.ast_for_uri(req_uri).unwrap();
-        // for link, reflink, footnote check if their definitions exist
-        let diagnostics = check_links(ast, req_uri, state)
-            .into_iter()
-            .map(|it| {
-                Diagnostic {
-                    range: it.range,
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    code: Some(NumberOrString::Number(
-                        it.error_code()
-                            .try_into()
-                            .expect("error code value to large for i32"),
-                    )),
-                    code_description: None,
-                    source: Some("md-lsp".to_string()),
-                    message: it.message,
-                    related_information: None, // might be interesting
-                    tags: None,
-                    data: None,
-                }
-            })
-            .collect::<Vec<_>>();
+        if self.pull_diagnostics {
+            return Ok(());
+        }
+        let diagnostics = diagnostics(req_uri, state);
 
         let diagnostic_params = PublishDiagnosticsParams {
             uri: req_uri.clone(),
@@ -180,6 +323,20 @@ impl Server {
         Ok(())
     }
 
+    fn clear_diagnostics(&self, uri: &Url) -> Result<()> {
+        let diagnostic_params = PublishDiagnosticsParams {
+            uri: uri.clone(),
+            diagnostics: Vec::new(),
+            version: None,
+        };
+        let resp = Notification::new(
+            "textDocument/publishDiagnostics".to_string(),
+            diagnostic_params,
+        );
+        self.connection.sender.send(Message::Notification(resp))?;
+        Ok(())
+    }
+
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_definition
     fn handle_defintion(&self, req: lsp_server::Request, state: &mut State) -> Result<()> {
         let params: GotoDefinitionParams = serde_json::from_value(req.params)?;
@@ -227,6 +384,13 @@ impl Server {
         self.send_result(req.id, result)
     }
 
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_onTypeFormatting
+    fn handle_on_type_formatting(&self, req: lsp_server::Request, state: &mut State) -> Result<()> {
+        let params: DocumentOnTypeFormattingParams = serde_json::from_value(req.params)?;
+        let result = on_type_formatting(&params, state);
+        self.send_result(req.id, result)
+    }
+
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_prepareRename
     fn handle_prepare_rename(&self, req: lsp_server::Request, state: &State) -> Result<()> {
         let params: TextDocumentPositionParams = serde_json::from_value(req.params)?;
@@ -246,10 +410,134 @@ impl Server {
         self.send_result(req.id, result)
     }
 
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_willRenameFiles
+    fn handle_will_rename_files(&self, req: lsp_server::Request, state: &State) -> Result<()> {
+        let params: RenameFilesParams = serde_json::from_value(req.params)?;
+
+        let result = will_rename_files(&params, state).map(|changes| WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        });
+        self.send_result(req.id, result)
+    }
+
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_didChangeWatchedFiles
-    fn handle_did_change_watched_files(&self, not: lsp_server::Notification) -> Result<()> {
-        log::info!("HANDLE DID CHANGE WATCHED FILE: {:?}", not);
-        Ok(())
+    fn handle_did_change_watched_files(
+        &self,
+        not: lsp_server::Notification,
+        state: &mut State,
+    ) -> Result<()> {
+        let params: DidChangeWatchedFilesParams = serde_json::from_value(not.params)?;
+        let mut project_settings_changed = false;
+        let mut changed = HashSet::new();
+        let mut deleted = HashSet::new();
+        for event in params.changes {
+            if event
+                .uri
+                .to_file_path()
+                .is_ok_and(|path| path.file_name().is_some_and(|name| name == PROJECT_FILE))
+            {
+                project_settings_changed = true;
+            }
+            match event.typ {
+                // the glob of the watcher also matches e.g. `target/` and `.git/`
+                FileChangeType::CREATED | FileChangeType::CHANGED
+                    if !state.is_ignored(&event.uri) =>
+                {
+                    changed.insert(event.uri);
+                }
+                // untracked files, like ignored ones, don't affect anything
+                FileChangeType::DELETED if state.is_tracked(&event.uri) => {
+                    changed.remove(&event.uri);
+                    deleted.insert(event.uri);
+                }
+                _ => {}
+            }
+        }
+        // files with links to deleted files need new diagnostics, their links
+        // only resolve before the change
+        let mut affected: HashSet<Url> = state.files_linking_to_any(&deleted).into_iter().collect();
+        let mut md_file_deleted = false;
+        for uri in &deleted {
+            let was_md_file = state.md_files.contains_key(uri);
+            state.remove_file(uri);
+            // opened files stay
+            if was_md_file && !state.md_files.contains_key(uri) {
+                md_file_deleted = true;
+                self.clear_diagnostics(uri)?;
+            }
+        }
+        for uri in &changed {
+            state.load_file(uri);
+        }
+        affected.extend(state.files_linking_to_any(&changed));
+        affected.extend(changed);
+        if project_settings_changed {
+            let reindex = state.load_project_settings();
+            return self.settings_changed(reindex, state);
+        }
+        affected.retain(|uri| state.md_files.contains_key(uri));
+        for uri in &affected {
+            self.handle_diagnostic(uri, state)?;
+        }
+        if affected.is_empty() && !md_file_deleted {
+            return Ok(());
+        }
+        self.refresh_diagnostics()
+    }
+
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_didChangeWorkspaceFolders
+    fn handle_did_change_workspace_folders(
+        &self,
+        not: lsp_server::Notification,
+        state: &mut State,
+    ) -> Result<()> {
+        let params: DidChangeWorkspaceFoldersParams = serde_json::from_value(not.params)?;
+        for folder in params.event.removed {
+            for uri in state.remove_workspace_folder(&folder.uri) {
+                self.clear_diagnostics(&uri)?;
+            }
+        }
+        for folder in &params.event.added {
+            state.add_workspace_folder(folder.clone());
+        }
+        let reindex = state.load_project_settings();
+        if !reindex {
+            state.index_md_files(&params.event.added);
+        }
+        // links might resolve differently with other workspace folders
+        self.settings_changed(reindex, state)
+    }
+
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_diagnostic
+    fn handle_document_diagnostic(&self, req: lsp_server::Request, state: &State) -> Result<()> {
+        let params: DocumentDiagnosticParams = serde_json::from_value(req.params)?;
+        let result = document_diagnostic(&params, state);
+        self.send_result(req.id, result)
+    }
+
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_diagnostic
+    fn handle_workspace_diagnostic(&self, req: lsp_server::Request, state: &State) -> Result<()> {
+        let params: WorkspaceDiagnosticParams = serde_json::from_value(req.params)?;
+        let result = workspace_diagnostic(&params, state);
+        self.send_result(req.id, result)
+    }
+
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_didChangeConfiguration
+    fn handle_did_change_configuration(
+        &self,
+        not: lsp_server::Notification,
+        state: &mut State,
+    ) -> Result<()> {
+        // clients supporting `workspace/configuration` usually send no
+        // settings with the notification
+        if self.workspace_configuration {
+            return self.request_configuration();
+        }
+        let params: DidChangeConfigurationParams = serde_json::from_value(not.params)?;
+        let reindex = state.set_client_settings(params.settings);
+        self.settings_changed(reindex, state)
     }
 
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_codeAction
@@ -259,6 +547,13 @@ impl Server {
         self.send_result(req.id, result)
     }
 
+    /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#codeAction_resolve
+    fn handle_code_action_resolve(&self, req: lsp_server::Request, state: &State) -> Result<()> {
+        let code_action: CodeAction = serde_json::from_value(req.params)?;
+        let result = resolve_code_action(code_action, state);
+        self.send_result(req.id, result)
+    }
+
     /// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_completion
     fn handle_completion(&self, req: lsp_server::Request, state: &State) -> Result<()> {
         let params: CompletionParams = serde_json::from_value(req.params)?;
@@ -266,3 +561,83 @@ impl Server {
         self.send_result(req.id, result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configuration_response_after_other_response() {
+        let (connection, client) = Connection::memory();
+        let mut server = Server::new(connection);
+        server.enable_workspace_configuration();
+        let mut state = State::new();
+        server.register_file_watchers().unwrap();
+        server.request_configuration().unwrap();
+        let ids: Vec<RequestId> = client
+            .receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                Message::Request(req) => Some(req.id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids.len(), 2);
+
+        server
+            .handle_response(Response::new_ok(ids[0].clone(), ()), &mut state)
+            .unwrap();
+        let settings = serde_json::json!([{ "formatting": { "lineWidth": 42 } }]);
+        server
+            .handle_response(Response::new_ok(ids[1].clone(), settings), &mut state)
+            .unwrap();
+        assert_eq!(state.settings().formatting.line_width, Some(42));
+    }
+
+    #[test]
+    fn test_deleted_watched_files() {
+        let (connection, client) = Connection::memory();
+        let server = Server::new(connection);
+        let mut state = State::new();
+        let root = std::env::current_dir().unwrap();
+        let uri = |path: &str| Url::from_file_path(root.join(path)).unwrap();
+        state.set_buffer(&uri("a.md"), "# A\n".to_string());
+        state.set_buffer(&uri("b.md"), "[a](a.md)\n".to_string());
+        let deleted = |paths: &[&str]| {
+            let params = DidChangeWatchedFilesParams {
+                changes: paths
+                    .iter()
+                    .map(|path| lsp_types::FileEvent::new(uri(path), FileChangeType::DELETED))
+                    .collect(),
+            };
+            Notification::new(DidChangeWatchedFiles::METHOD.to_string(), params)
+        };
+        let published = |client: &Connection| -> Vec<Url> {
+            client
+                .receiver
+                .try_iter()
+                .filter_map(|message| match message {
+                    Message::Notification(not) => {
+                        serde_json::from_value::<PublishDiagnosticsParams>(not.params)
+                            .ok()
+                            .map(|params| params.uri)
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // untracked files, e.g. after `cargo clean`
+        server
+            .handle_did_change_watched_files(deleted(&["target/x.md", "target/y"]), &mut state)
+            .unwrap();
+        assert!(published(&client).is_empty());
+
+        server
+            .handle_did_change_watched_files(deleted(&["a.md"]), &mut state)
+            .unwrap();
+        let mut published = published(&client);
+        published.sort();
+        assert_eq!(published, vec![uri("a.md"), uri("b.md")]);
+    }
+}
@@ -0,0 +1,388 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use lsp_types::DiagnosticSeverity;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Section of the settings in `workspace/didChangeConfiguration` and
+/// `workspace/configuration`
+pub const SECTION: &str = "md-lsp";
+
+/// Project settings at the root of a workspace folder, overriding the
+/// settings of the client
+pub const PROJECT_FILE: &str = "md-lsp.toml";
+
+/// Settings from the client, sent as `initializationOptions`, with
+/// `workspace/didChangeConfiguration` or as response to
+/// `workspace/configuration`, and from `md-lsp.toml`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    /// Severity per diagnostic rule, keyed by rule name like `FileNotFound`
+    pub diagnostics: HashMap<String, RuleSeverity>,
+    pub formatting: FormattingSettings,
+    pub code_blocks: CodeBlockSettings,
+    /// Extensions of markdown files, without the leading `.`
+    pub file_extensions: Vec<String>,
+    /// Realign tables on typing `|` or Tab, only read from
+    /// `initializationOptions` to offer on type formatting
+    pub format_tables_on_type: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            diagnostics: HashMap::new(),
+            formatting: FormattingSettings::default(),
+            code_blocks: CodeBlockSettings::default(),
+            file_extensions: vec!["md".to_string()],
+            format_tables_on_type: false,
+        }
+    }
+}
+
+/// Options of the markdown formatter, unset options fall back to `dprint.json`
+/// and `.editorconfig`. List items are always indented by the width of their
+/// marker, so there are no indentation options.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FormattingSettings {
+    pub line_width: Option<u32>,
+    pub new_line_kind: Option<NewLineKind>,
+    pub text_wrap: Option<TextWrap>,
+    pub emphasis_kind: Option<EmphasisKind>,
+    pub strong_kind: Option<StrongKind>,
+    pub ignore_directive: Option<String>,
+    pub ignore_file_directive: Option<String>,
+    pub ignore_start_directive: Option<String>,
+    pub ignore_end_directive: Option<String>,
+}
+
+impl FormattingSettings {
+    /// Options of `self`, unset ones are taken from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            line_width: self.line_width.or(fallback.line_width),
+            new_line_kind: self.new_line_kind.or(fallback.new_line_kind),
+            text_wrap: self.text_wrap.or(fallback.text_wrap),
+            emphasis_kind: self.emphasis_kind.or(fallback.emphasis_kind),
+            strong_kind: self.strong_kind.or(fallback.strong_kind),
+            ignore_directive: self.ignore_directive.or(fallback.ignore_directive),
+            ignore_file_directive: self
+                .ignore_file_directive
+                .or(fallback.ignore_file_directive),
+            ignore_start_directive: self
+                .ignore_start_directive
+                .or(fallback.ignore_start_directive),
+            ignore_end_directive: self.ignore_end_directive.or(fallback.ignore_end_directive),
+        }
+    }
+}
+
+/// Formatting of fenced code blocks, by their language tag
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CodeBlockSettings {
+    /// Format JSON, TOML and YAML in-process
+    pub builtin: bool,
+    /// External formatters, reading the code from stdin and writing the
+    /// formatted code to stdout. They take precedence over the builtin ones.
+    pub commands: HashMap<String, FormatCommand>,
+}
+
+impl Default for CodeBlockSettings {
+    fn default() -> Self {
+        Self {
+            builtin: true,
+            commands: HashMap::new(),
+        }
+    }
+}
+
+/// Either a command line like `rustfmt --emit stdout`, split at whitespace,
+/// or the program and its arguments
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum FormatCommand {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl FormatCommand {
+    pub fn args(&self) -> Vec<&str> {
+        match self {
+            FormatCommand::Line(line) => line.split_whitespace().collect(),
+            FormatCommand::Args(args) => args.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NewLineKind {
+    Auto,
+    Lf,
+    Crlf,
+}
+
+impl From<NewLineKind> for dprint_core::configuration::NewLineKind {
+    fn from(new_line_kind: NewLineKind) -> Self {
+        match new_line_kind {
+            NewLineKind::Auto => Self::Auto,
+            NewLineKind::Lf => Self::LineFeed,
+            NewLineKind::Crlf => Self::CarriageReturnLineFeed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextWrap {
+    Always,
+    Never,
+    Maintain,
+}
+
+impl From<TextWrap> for dprint_plugin_markdown::configuration::TextWrap {
+    fn from(text_wrap: TextWrap) -> Self {
+        match text_wrap {
+            TextWrap::Always => Self::Always,
+            TextWrap::Never => Self::Never,
+            TextWrap::Maintain => Self::Maintain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmphasisKind {
+    Asterisks,
+    Underscores,
+}
+
+impl From<EmphasisKind> for dprint_plugin_markdown::configuration::EmphasisKind {
+    fn from(emphasis_kind: EmphasisKind) -> Self {
+        match emphasis_kind {
+            EmphasisKind::Asterisks => Self::Asterisks,
+            EmphasisKind::Underscores => Self::Underscores,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrongKind {
+    Asterisks,
+    Underscores,
+}
+
+impl From<StrongKind> for dprint_plugin_markdown::configuration::StrongKind {
+    fn from(strong_kind: StrongKind) -> Self {
+        match strong_kind {
+            StrongKind::Asterisks => Self::Asterisks,
+            StrongKind::Underscores => Self::Underscores,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleSeverity {
+    Off,
+    Hint,
+    #[serde(alias = "info")]
+    Information,
+    Warning,
+    Error,
+}
+
+impl RuleSeverity {
+    /// `None` for disabled rules
+    pub fn diagnostic_severity(self) -> Option<DiagnosticSeverity> {
+        match self {
+            RuleSeverity::Off => None,
+            RuleSeverity::Hint => Some(DiagnosticSeverity::HINT),
+            RuleSeverity::Information => Some(DiagnosticSeverity::INFORMATION),
+            RuleSeverity::Warning => Some(DiagnosticSeverity::WARNING),
+            RuleSeverity::Error => Some(DiagnosticSeverity::ERROR),
+        }
+    }
+}
+
+impl Settings {
+    /// Settings from JSON, either directly or in a `md-lsp` section. Invalid
+    /// settings are logged and replaced by the defaults.
+    pub fn from_value(value: Value) -> Self {
+        let value = unwrap_section(value);
+        if value.is_null() {
+            return Self::default();
+        }
+        serde_json::from_value(value).unwrap_or_else(|e| {
+            log::warn!("Invalid settings, using defaults: {e}");
+            Self::default()
+        })
+    }
+
+    /// Settings of the client, overridden by the project settings. External
+    /// code block formatters are only taken from the client, a project file of
+    /// an untrusted repository must not run commands.
+    pub fn merged(client: &Value, project: &Value) -> Self {
+        let mut value = unwrap_section(client.clone());
+        let mut project = project.clone();
+        let project_commands = project
+            .get_mut("codeBlocks")
+            .and_then(Value::as_object_mut)
+            .and_then(|code_blocks| code_blocks.remove("commands"));
+        if project_commands.is_some() {
+            log::warn!("Ignoring `codeBlocks.commands` of {PROJECT_FILE}, set them in the client");
+        }
+        merge(&mut value, project);
+        Self::from_value(value)
+    }
+
+    /// Configured severity of the diagnostic rule `rule_name`
+    pub fn rule_severity(&self, rule_name: &str) -> Option<RuleSeverity> {
+        self.diagnostics.get(rule_name).copied()
+    }
+
+    pub fn is_md_file(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| {
+            self.file_extensions
+                .iter()
+                .any(|md_ext| ext == md_ext.as_str())
+        })
+    }
+
+    /// `file` without its markdown extension
+    pub fn strip_md_extension<'a>(&self, file: &'a str) -> &'a str {
+        self.file_extensions
+            .iter()
+            .find_map(|ext| file.strip_suffix(ext)?.strip_suffix('.'))
+            .unwrap_or(file)
+    }
+}
+
+/// Content of `md-lsp.toml` in `root` as JSON, `Null` if there is none
+pub fn read_project_settings(root: &Path) -> Value {
+    let path = root.join(PROJECT_FILE);
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Value::Null;
+    };
+    toml::from_str(&content).unwrap_or_else(|e| {
+        log::warn!("Invalid {}: {e}", path.display());
+        Value::Null
+    })
+}
+
+fn unwrap_section(value: Value) -> Value {
+    match value {
+        Value::Object(mut map) if map.contains_key(SECTION) => map.remove(SECTION).unwrap(),
+        value => value,
+    }
+}
+
+/// Merges `overrides` into `base`, objects are merged recursively
+pub fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (_, Value::Null) => {}
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_settings_from_value() {
+        let settings = Settings::from_value(json!({
+            "md-lsp": {
+                "diagnostics": {
+                    "ExternalHeadingNotFound": "warning",
+                    "UnusedDefinition": "off",
+                    "FileNotFound": "info"
+                }
+            }
+        }));
+        assert_eq!(
+            settings.rule_severity("ExternalHeadingNotFound"),
+            Some(RuleSeverity::Warning)
+        );
+        assert_eq!(
+            settings.rule_severity("UnusedDefinition"),
+            Some(RuleSeverity::Off)
+        );
+        assert_eq!(
+            settings.rule_severity("FileNotFound"),
+            Some(RuleSeverity::Information)
+        );
+        assert_eq!(settings.rule_severity("HeadingNotFound"), None);
+
+        let invalid = Settings::from_value(json!({ "diagnostics": { "FileNotFound": "loud" } }));
+        assert!(invalid.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_settings_merged() {
+        let client = json!({
+            "md-lsp": {
+                "diagnostics": { "FileNotFound": "warning" },
+                "formatting": { "lineWidth": 100, "strongKind": "underscores" },
+                "codeBlocks": { "commands": { "rust": "rustfmt" } }
+            }
+        });
+        let project: Value = toml::from_str(
+            r#"
+            fileExtensions = ["md", "markdown"]
+
+            [formatting]
+            lineWidth = 120
+            textWrap = "maintain"
+
+            [codeBlocks]
+            builtin = false
+            commands = { rust = "curl example.com", sh = "sh" }
+            "#,
+        )
+        .unwrap();
+        let settings = Settings::merged(&client, &project);
+        assert_eq!(
+            settings.rule_severity("FileNotFound"),
+            Some(RuleSeverity::Warning)
+        );
+        assert_eq!(
+            settings.formatting,
+            FormattingSettings {
+                line_width: Some(120),
+                text_wrap: Some(TextWrap::Maintain),
+                strong_kind: Some(StrongKind::Underscores),
+                ..Default::default()
+            }
+        );
+        // commands of the project file are ignored
+        assert!(!settings.code_blocks.builtin);
+        assert_eq!(
+            settings.code_blocks.commands,
+            HashMap::from([(
+                "rust".to_string(),
+                FormatCommand::Line("rustfmt".to_string())
+            )])
+        );
+        assert!(settings.is_md_file(Path::new("/notes/a.markdown")));
+        assert!(!settings.is_md_file(Path::new("/notes/a.txt")));
+        assert_eq!(settings.strip_md_extension("dir/a.markdown"), "dir/a");
+        assert_eq!(settings.strip_md_extension("dir/a"), "dir/a");
+
+        assert_eq!(
+            Settings::merged(&Value::Null, &Value::Null),
+            Settings::default()
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use markdown::mdast::{Heading, Node};
+
+use crate::ast::{inline_text, TraverseNode};
+
+/// Anchor of a heading as generated by GitHub: lowercase, punctuation and
+/// symbols removed, spaces replaced by `-`
+pub fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+/// Generates unique slugs, duplicates get a numeric suffix (`foo`, `foo-1`,
+/// `foo-2`, ...)
+#[derive(Debug, Default)]
+pub struct Slugger {
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn slug(&mut self, text: &str) -> String {
+        let original_slug = slug(text);
+        let mut result = original_slug.clone();
+        while self.occurrences.contains_key(&result) {
+            let count = self.occurrences.entry(original_slug.clone()).or_default();
+            *count += 1;
+            result = format!("{original_slug}-{count}");
+        }
+        self.occurrences.insert(result.clone(), 0);
+        result
+    }
+}
+
+/// Slug of a heading, without taking other headings into account
+pub fn heading_slug(heading: &Heading) -> String {
+    slug(&inline_text(&heading.children))
+}
+
+/// All headings in `ast` with their unique slugs, in document order
+pub fn heading_slugs(ast: &Node) -> Vec<(&Heading, String)> {
+    let mut slugger = Slugger::new();
+    ast.find_headings()
+        .into_iter()
+        .map(|heading| (heading, slugger.slug(&inline_text(&heading.children))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(slug("Heading 1"), "heading-1");
+        assert_eq!(slug("What's new?"), "whats-new");
+        assert_eq!(slug("The `State` struct"), "the-state-struct");
+        assert_eq!(slug("Über 😀 Emoji"), "über--emoji");
+        assert_eq!(slug("snake_case and-dashes"), "snake_case-and-dashes");
+    }
+
+    #[test]
+    fn test_slugger_duplicates() {
+        let mut slugger = Slugger::new();
+        assert_eq!(slugger.slug("Setup"), "setup");
+        assert_eq!(slugger.slug("Setup"), "setup-1");
+        assert_eq!(slugger.slug("Setup 1"), "setup-1-1");
+        assert_eq!(slugger.slug("Setup"), "setup-2");
+    }
+
+    #[test]
+    fn test_heading_slugs() {
+        let md = "# Intro\n\n## The `State` *struct*\n\n# Intro\n";
+        let ast = markdown::to_mdast(md, &markdown::ParseOptions::gfm()).unwrap();
+        let slugs: Vec<String> = heading_slugs(&ast).into_iter().map(|(_, s)| s).collect();
+        assert_eq!(slugs, vec!["intro", "the-state-struct", "intro-1"]);
+    }
+}
@@ -1,26 +1,88 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
 };
 
-use ignore::Walk;
-use itertools::Itertools;
-use lsp_types::{Range, Url, WorkspaceFolder};
-use markdown::mdast::Node;
+use ignore::WalkBuilder;
+use lsp_types::{Position, Range, TextDocumentContentChangeEvent, Url, WorkspaceFolder};
+use markdown::{mdast::Node, unist};
+use serde_json::Value;
 
-use crate::links::parse_wiki_links;
+use crate::{
+    ast::TraverseNode,
+    diagnostics::DiagnosticsCache,
+    line_index::{LineIndex, PositionEncoding},
+    links::{parse_wiki_links, resolve_asset, resolve_link},
+    settings::{merge, read_project_settings, Settings},
+};
 
 #[derive(Debug)]
 pub struct MdFile {
     buffer: String,
     pub ast: Node,
+    line_index: LineIndex,
+    /// Buffer was changed since `ast` was last parsed
+    dirty: bool,
+}
+
+impl MdFile {
+    fn new(buffer: String, encoding: PositionEncoding) -> Self {
+        let ast = parse_md(&buffer);
+        let line_index = LineIndex::new(&buffer, encoding);
+        Self {
+            buffer,
+            ast,
+            line_index,
+            dirty: false,
+        }
+    }
+
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// LSP range for a position in the AST
+    pub fn range(&self, position: &unist::Position) -> Range {
+        self.line_index.unist_range(&self.buffer, position)
+    }
+
+    /// LSP range between two byte offsets of the buffer
+    pub fn range_from_offsets(&self, start: usize, end: usize) -> Range {
+        self.line_index.range(&self.buffer, start, end)
+    }
+
+    /// Byte offset in the buffer for a LSP position
+    pub fn offset(&self, pos: &Position) -> usize {
+        self.line_index.offset(&self.buffer, pos)
+    }
+
+    /// LSP position for a byte offset in the buffer
+    pub fn position(&self, offset: usize) -> Position {
+        self.line_index.position(&self.buffer, offset)
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct State {
     pub md_files: HashMap<Url, MdFile>,
-    workspace_folder: Option<WorkspaceFolder>,
+    /// Non-markdown files like images, PDFs or source files
+    asset_files: HashSet<Url>,
+    /// Files opened in the editor, their buffers are managed by the client
+    open_files: HashSet<Url>,
+    workspace_folders: Vec<WorkspaceFolder>,
+    position_encoding: PositionEncoding,
+    /// The client can resolve the edits of code actions lazily
+    code_action_resolve: bool,
+    /// The client supports `CreateFile` operations in workspace edits
+    create_files: bool,
+    /// Settings sent by the client
+    client_settings: Value,
+    /// Settings from `md-lsp.toml` of the workspace folders
+    project_settings: Value,
+    /// Client settings, overridden by the project settings
+    settings: Settings,
+    diagnostics_cache: DiagnosticsCache,
 }
 
 impl State {
@@ -28,23 +90,312 @@ impl State {
         Self::default()
     }
 
-    pub fn workspace_folder(&self) -> Option<&WorkspaceFolder> {
-        self.workspace_folder.as_ref()
+    pub fn workspace_folders(&self) -> &[WorkspaceFolder] {
+        &self.workspace_folders
+    }
+
+    pub fn add_workspace_folder(&mut self, workspace_folder: WorkspaceFolder) {
+        self.workspace_folders.push(workspace_folder);
+        // root-relative links resolve differently
+        self.diagnostics_cache.clear();
+    }
+
+    /// Removes the workspace folder and all files not belonging to another
+    /// workspace folder, returns the Urls of the removed files
+    pub fn remove_workspace_folder(&mut self, uri: &Url) -> Vec<Url> {
+        self.workspace_folders.retain(|wsf| &wsf.uri != uri);
+        self.diagnostics_cache.clear();
+        let removed: Vec<Url> = self
+            .md_files
+            .keys()
+            .filter(|url| {
+                !self.open_files.contains(*url) && self.workspace_root_for_uri(url).is_none()
+            })
+            .cloned()
+            .collect();
+        for url in &removed {
+            self.md_files.remove(url);
+        }
+        let removed_assets: Vec<Url> = self
+            .asset_files
+            .iter()
+            .filter(|url| self.workspace_root_for_uri(url).is_none())
+            .cloned()
+            .collect();
+        for url in &removed_assets {
+            self.asset_files.remove(url);
+        }
+        removed
+    }
+
+    /// Path of the (innermost) workspace folder containing `uri`
+    pub fn workspace_root_for_uri(&self, uri: &Url) -> Option<PathBuf> {
+        let file_path = uri.to_file_path().ok()?;
+        self.workspace_folders
+            .iter()
+            .filter_map(|wsf| wsf.uri.to_file_path().ok())
+            .filter(|root| file_path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+    }
+
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.position_encoding
+    }
+
+    /// Has to be set before any buffers are added
+    pub fn set_position_encoding(&mut self, position_encoding: PositionEncoding) {
+        self.position_encoding = position_encoding;
+    }
+
+    pub fn code_action_resolve(&self) -> bool {
+        self.code_action_resolve
+    }
+
+    pub fn set_code_action_resolve(&mut self, code_action_resolve: bool) {
+        self.code_action_resolve = code_action_resolve;
+    }
+
+    pub fn create_files(&self) -> bool {
+        self.create_files
     }
 
-    pub fn set_workspace_folder(&mut self, workspace_folder: WorkspaceFolder) {
-        self.workspace_folder = Some(workspace_folder);
+    pub fn set_create_files(&mut self, create_files: bool) {
+        self.create_files = create_files;
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Sets the settings of the client, returns whether the markdown file
+    /// extensions changed and the workspace has to be re-indexed
+    pub fn set_client_settings(&mut self, client_settings: Value) -> bool {
+        self.client_settings = client_settings;
+        self.update_settings()
+    }
+
+    /// (Re-)reads `md-lsp.toml` of all workspace folders, later folders
+    /// override earlier ones. Returns whether the markdown file extensions
+    /// changed and the workspace has to be re-indexed.
+    pub fn load_project_settings(&mut self) -> bool {
+        let mut project_settings = Value::Null;
+        for root in self
+            .workspace_folders
+            .iter()
+            .filter_map(|wsf| wsf.uri.to_file_path().ok())
+        {
+            merge(&mut project_settings, read_project_settings(&root));
+        }
+        self.project_settings = project_settings;
+        self.update_settings()
+    }
+
+    fn update_settings(&mut self) -> bool {
+        let settings = Settings::merged(&self.client_settings, &self.project_settings);
+        let extensions_changed = settings.file_extensions != self.settings.file_extensions;
+        self.settings = settings;
+        self.diagnostics_cache.clear();
+        extensions_changed
+    }
+
+    /// Indexes the workspace folders again, e.g. after the markdown file
+    /// extensions changed. Returns the Urls of files that are no longer
+    /// markdown files.
+    pub fn reindex(&mut self) -> Vec<Url> {
+        let removed: Vec<Url> = self
+            .md_files
+            .keys()
+            .filter(|uri| {
+                !self.open_files.contains(*uri)
+                    && uri.to_file_path().is_ok_and(|path| !self.is_md_file(&path))
+            })
+            .cloned()
+            .collect();
+        for uri in &removed {
+            self.md_files.remove(uri);
+        }
+        self.asset_files.clear();
+        let workspace_folders = self.workspace_folders.clone();
+        self.index_md_files(&workspace_folders);
+        removed
+    }
+
+    /// Whether `path` has one of the configured markdown file extensions
+    pub fn is_md_file(&self, path: &Path) -> bool {
+        self.settings.is_md_file(path)
     }
 
     pub fn set_buffer(&mut self, uri: &Url, buffer: String) {
-        let ast = markdown::to_mdast(&buffer, &markdown::ParseOptions::gfm()).unwrap();
-        if let Some(md_file) = self.md_files.get_mut(uri) {
-            md_file.ast = ast;
-            parse_wiki_links(&mut md_file.ast);
-            md_file.buffer = buffer;
-        } else {
-            let md_file = MdFile { buffer, ast };
-            self.md_files.insert(uri.clone(), md_file);
+        let added = self.insert_buffer(uri, buffer);
+        self.invalidate_diagnostics(&HashSet::from([uri.clone()]), added);
+    }
+
+    /// Returns whether the file is new
+    fn insert_buffer(&mut self, uri: &Url, buffer: String) -> bool {
+        let md_file = MdFile::new(buffer, self.position_encoding);
+        self.md_files.insert(uri.clone(), md_file).is_none()
+    }
+
+    pub fn open_file(&mut self, uri: &Url, buffer: String) {
+        self.open_files.insert(uri.clone());
+        self.set_buffer(uri, buffer);
+    }
+
+    /// After closing, the content on disk is the truth again
+    pub fn close_file(&mut self, uri: &Url) {
+        self.open_files.remove(uri);
+        self.load_file(uri);
+    }
+
+    /// (Re-)loads the file from disk, unless it is opened in the editor.
+    /// Non-markdown files are only tracked as assets.
+    pub fn load_file(&mut self, uri: &Url) {
+        if let Some(added) = self.read_file(uri) {
+            self.invalidate_diagnostics(&HashSet::from([uri.clone()]), added);
+        }
+    }
+
+    /// Loads the file without invalidating cached diagnostics, returns whether
+    /// a new file was added or `None` if nothing changed
+    fn read_file(&mut self, uri: &Url) -> Option<bool> {
+        if self.open_files.contains(uri) {
+            return None;
+        }
+        let path = uri.to_file_path().ok()?;
+        if !self.is_md_file(&path) {
+            return (path.is_file() && self.asset_files.insert(uri.clone())).then_some(true);
+        }
+        match fs::read_to_string(&path) {
+            Ok(buffer) => Some(self.insert_buffer(uri, buffer)),
+            Err(e) => {
+                log::warn!("Could not read {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Removes the file from the index, unless it is opened in the editor
+    pub fn remove_file(&mut self, uri: &Url) {
+        if !self.open_files.contains(uri) {
+            self.md_files.remove(uri);
+        }
+        self.asset_files.remove(uri);
+        self.diagnostics_cache.clear();
+    }
+
+    /// Whether `uri` is indexed as markdown file or asset
+    pub fn is_tracked(&self, uri: &Url) -> bool {
+        self.md_files.contains_key(uri) || self.asset_files.contains(uri)
+    }
+
+    pub fn asset_files(&self) -> impl Iterator<Item = &Url> {
+        self.asset_files.iter()
+    }
+
+    pub fn asset_file(&self, uri: &Url) -> Option<&Url> {
+        self.asset_files.get(uri)
+    }
+
+    /// All files containing a link that resolves to `uri`
+    pub fn files_linking_to(&self, uri: &Url) -> Vec<Url> {
+        self.files_linking_to_any(&HashSet::from([uri.clone()]))
+    }
+
+    /// All files containing a link that resolves to one of `uris`
+    pub fn files_linking_to_any(&self, uris: &HashSet<Url>) -> Vec<Url> {
+        if uris.is_empty() {
+            return Vec::new();
+        }
+        self.md_files
+            .iter()
+            .filter(|(url, md_file)| {
+                md_file.ast.find_links().into_iter().any(|link| {
+                    resolve_link(link, url, self)
+                        .file_uri()
+                        .is_some_and(|uri| uris.contains(uri))
+                }) || md_file.ast.find_images().into_iter().any(|image| {
+                    resolve_asset(&image.url, url, self).is_some_and(|uri| uris.contains(uri))
+                })
+            })
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+
+    /// Whether `uri` is left out when indexing its workspace folder, e.g.
+    /// because it is ignored by `.gitignore`
+    pub fn is_ignored(&self, uri: &Url) -> bool {
+        match (self.workspace_root_for_uri(uri), uri.to_file_path()) {
+            (Some(root), Ok(path)) => is_ignored(&root, &path),
+            _ => false,
+        }
+    }
+
+    /// Applies a (ranged) content change to the buffer of `uri`. The AST is not
+    /// updated until `reparse_dirty` is called, so consecutive changes only get
+    /// parsed once. A ranged change without a buffer is applied to the file on
+    /// disk.
+    pub fn apply_change(&mut self, uri: &Url, change: TextDocumentContentChangeEvent) {
+        let Some(md_file) = self.md_files.get_mut(uri) else {
+            if change.range.is_none() {
+                self.set_buffer(uri, change.text);
+                return;
+            }
+            let path = uri.to_file_path().ok();
+            match path.and_then(|path| fs::read_to_string(path).ok()) {
+                Some(buffer) => {
+                    log::warn!("No buffer for {uri}, applying change to the file on disk");
+                    self.set_buffer(uri, buffer);
+                    self.apply_change(uri, change);
+                }
+                None => log::warn!("No buffer for {uri} and no file on disk, dropping change"),
+            }
+            return;
+        };
+        match change.range {
+            Some(range) => {
+                let start = md_file.offset(&range.start);
+                let end = md_file.offset(&range.end).max(start);
+                md_file.buffer.replace_range(start..end, &change.text);
+            }
+            None => md_file.buffer = change.text,
+        }
+        md_file.line_index = LineIndex::new(&md_file.buffer, self.position_encoding);
+        md_file.dirty = true;
+    }
+
+    /// Re-parses the ASTs of all buffers changed since the last call, returns
+    /// their Urls
+    pub fn reparse_dirty(&mut self) -> Vec<Url> {
+        let reparsed: Vec<Url> = self
+            .md_files
+            .iter_mut()
+            .filter(|(_, md_file)| md_file.dirty)
+            .map(|(uri, md_file)| {
+                md_file.ast = parse_md(&md_file.buffer);
+                md_file.dirty = false;
+                uri.clone()
+            })
+            .collect();
+        if !reparsed.is_empty() {
+            self.invalidate_diagnostics(&reparsed.iter().cloned().collect(), false);
+        }
+        reparsed
+    }
+
+    pub fn diagnostics_cache(&self) -> &DiagnosticsCache {
+        &self.diagnostics_cache
+    }
+
+    /// Drops the cached diagnostics of the changed files `uris` and of the
+    /// files linking to them. Links resolve differently after a file was
+    /// `added`, so then all diagnostics are dropped.
+    fn invalidate_diagnostics(&self, uris: &HashSet<Url>, added: bool) {
+        if added {
+            self.diagnostics_cache.clear();
+            return;
+        }
+        for uri in uris.iter().cloned().chain(self.files_linking_to_any(uris)) {
+            self.diagnostics_cache.remove(&uri);
         }
     }
 
@@ -58,89 +409,99 @@ impl State {
             .map(|md_file| md_file.buffer.as_str())
     }
 
-    pub fn index_md_files(&mut self, workspace_folders: &[WorkspaceFolder]) {
-        let md_files = self.find_md_files(workspace_folders);
-        self.md_files = md_files
+    /// LSP range for a position in the AST of `uri`
+    pub fn range_for_uri(&self, uri: &Url, position: &unist::Position) -> Option<Range> {
+        self.md_files
+            .get(uri)
+            .map(|md_file| md_file.range(position))
+    }
+
+    /// Byte offset in the buffer of `uri` for a LSP position
+    pub fn offset_for_uri(&self, uri: &Url, pos: &Position) -> Option<usize> {
+        self.md_files.get(uri).map(|md_file| md_file.offset(pos))
+    }
+
+    /// Indexes all files of the workspace folders, returns the Urls of the
+    /// markdown files
+    pub fn index_md_files(&mut self, workspace_folders: &[WorkspaceFolder]) -> Vec<Url> {
+        let files = self.find_files(workspace_folders);
+        let md_files = files
             .into_iter()
-            .map(|file| {
+            .filter_map(|file| {
                 log::info!("INDEXING: {:#?}", &file);
-                let buffer = fs::read_to_string(&file).unwrap();
-                let mut ast = markdown::to_mdast(&buffer, &markdown::ParseOptions::gfm()).unwrap();
-                parse_wiki_links(&mut ast);
-                let uri = Url::from_file_path(&file).unwrap();
-
-                (uri, MdFile { buffer, ast })
+                let uri = Url::from_file_path(&file).ok()?;
+                self.read_file(&uri);
+                self.is_md_file(&file).then_some(uri)
             })
             .collect();
+        self.diagnostics_cache.clear();
+        md_files
     }
 
-    fn find_md_files(&self, workspace_folders: &[WorkspaceFolder]) -> Vec<PathBuf> {
-        let mut md_files = Vec::new();
+    /// All files, that are not ignored (e.g. by `.gitignore`)
+    fn find_files(&self, workspace_folders: &[WorkspaceFolder]) -> Vec<PathBuf> {
+        let mut files = Vec::new();
         for folder in workspace_folders {
             if let Ok(f) = folder.uri.to_file_path() {
-                for entry in Walk::new(f).flatten() {
-                    let path = entry.into_path();
-                    if path.extension().is_some_and(|ext| ext == "md") {
-                        md_files.push(path)
+                for entry in WalkBuilder::new(f).build().flatten() {
+                    if entry.file_type().is_some_and(|ft| ft.is_file()) {
+                        files.push(entry.into_path())
                     }
                 }
             }
         }
-        md_files
+        files
     }
 
     pub fn buffer_range_for_uri(&self, uri: &Url, range: &Range) -> Option<String> {
-        let doc = self.buffer_for_uri(uri)?;
-        let start_line = range.start.line as usize;
-        let end_line = range.end.line as usize;
-        let start_column = range.start.character as usize;
-        let end_column = range.end.character as usize;
-
-        let sliced = doc
-            .lines()
-            .enumerate()
-            .filter_map(|(line_num, line)| {
-                if line_num == start_line {
-                    line.get(start_column..)
-                } else if line_num == end_line {
-                    line.get(..end_column)
-                } else if line_num >= start_line && line_num <= end_line {
-                    Some(line)
-                } else {
-                    None
-                }
-            })
-            .join("\n");
-        Some(sliced)
+        let md_file = self.md_files.get(uri)?;
+        let start = md_file.offset(&range.start);
+        let end = md_file.offset(&range.end).max(start);
+        md_file.buffer.get(start..end).map(ToString::to_string)
     }
 
-    pub fn cursor_char(&self, uri: &Url, pos: &lsp_types::Position) -> Option<char> {
-        let doc = self.buffer_for_uri(uri)?;
-        let line = doc.lines().nth(pos.line as usize)?;
-        line.chars().nth((pos.character.checked_sub(1))? as usize)
+    /// The part of the buffer of `uri` between two byte offsets
+    pub fn buffer_slice_for_uri(&self, uri: &Url, start: usize, end: usize) -> Option<&str> {
+        self.buffer_for_uri(uri)?.get(start..end)
     }
 
-    pub fn peek_behind_position(&self, uri: &Url, pos: &lsp_types::Position) -> Option<char> {
-        let doc = self.buffer_for_uri(uri)?;
-        let line = doc.lines().nth(pos.line as usize)?;
-        line.chars().nth((pos.character.checked_sub(2))? as usize)
+    /// The character before the cursor
+    pub fn cursor_char(&self, uri: &Url, pos: &Position) -> Option<char> {
+        let md_file = self.md_files.get(uri)?;
+        let offset = md_file.offset(pos);
+        md_file.buffer[..offset].chars().next_back()
     }
 
-    pub fn get_file_list(&self) -> Vec<(&Url, String)> {
-        self.md_files
-            .keys()
-            .filter_map(|url| {
-                self.workspace_folder().and_then(|wsf| {
-                    let root = PathBuf::from(&wsf.uri.path());
-                    let file_path = url.to_file_path().ok()?;
-                    let path_from_root = path_from_root(&root, &file_path)?;
-                    Some((url, path_from_root))
-                })
-            })
-            .collect()
+    /// The character before `cursor_char`
+    pub fn peek_behind_position(&self, uri: &Url, pos: &Position) -> Option<char> {
+        let md_file = self.md_files.get(uri)?;
+        let offset = md_file.offset(pos);
+        md_file.buffer[..offset].chars().nth_back(1)
     }
 }
 
+/// AST of `buffer`, with wikilinks
+pub fn parse_md(buffer: &str) -> Node {
+    let mut ast = markdown::to_mdast(buffer, &markdown::ParseOptions::gfm()).unwrap();
+    parse_wiki_links(&mut ast, buffer);
+    ast
+}
+
+/// Whether indexing `root` skips `path`, because it is hidden, ignored (e.g.
+/// by `.gitignore`) or doesn't exist. Walks only the directories leading to
+/// `path`, with the same rules as `find_files`.
+fn is_ignored(root: &Path, path: &Path) -> bool {
+    if !path.starts_with(root) {
+        return false;
+    }
+    let target = path.to_path_buf();
+    !WalkBuilder::new(root)
+        .filter_entry(move |entry| target.starts_with(entry.path()))
+        .build()
+        .flatten()
+        .any(|entry| entry.path() == path)
+}
+
 pub fn path_from_root(from: &Path, to: &Path) -> Option<String> {
     if let Ok(rel) = to.strip_prefix(from) {
         Some(format!("/{}", rel.to_string_lossy()))
@@ -153,6 +514,8 @@ pub fn path_from_root(from: &Path, to: &Path) -> Option<String> {
 mod tests {
     use super::*;
 
+    use crate::ast::TraverseNode;
+
     fn create_workspacefolder() -> WorkspaceFolder {
         let workspacefolder_dir = std::env::current_dir().expect("Current directory should exist");
         let s = format!("file:///{}", workspacefolder_dir.to_str().unwrap());
@@ -167,7 +530,7 @@ mod tests {
     fn init_state() -> State {
         let workspace_folder = create_workspacefolder();
         let mut state = State::new();
-        state.set_workspace_folder(workspace_folder);
+        state.add_workspace_folder(workspace_folder);
         state
     }
 
@@ -182,6 +545,140 @@ mod tests {
         assert_eq!(from_root2, None);
     }
 
+    #[test]
+    fn test_apply_change() {
+        let uri = Url::parse("file:///test.md").unwrap();
+        let mut state = State::new();
+        state.set_buffer(&uri, "# Heading\n\nSome text\n".to_string());
+        state.apply_change(
+            &uri,
+            TextDocumentContentChangeEvent {
+                range: Some(Range {
+                    start: Position {
+                        line: 2,
+                        character: 5,
+                    },
+                    end: Position {
+                        line: 2,
+                        character: 9,
+                    },
+                }),
+                range_length: None,
+                text: "äöü".to_string(),
+            },
+        );
+        state.apply_change(
+            &uri,
+            TextDocumentContentChangeEvent {
+                range: Some(Range {
+                    start: Position {
+                        line: 2,
+                        character: 8,
+                    },
+                    end: Position {
+                        line: 2,
+                        character: 8,
+                    },
+                }),
+                range_length: None,
+                text: " [[link]]".to_string(),
+            },
+        );
+        assert_eq!(
+            state.buffer_for_uri(&uri),
+            Some("# Heading\n\nSome äöü [[link]]\n")
+        );
+        assert_eq!(state.reparse_dirty(), vec![uri.clone()]);
+        assert!(state.reparse_dirty().is_empty());
+        assert_eq!(state.ast_for_uri(&uri).unwrap().find_links().len(), 1);
+
+        // without a buffer, the change is applied to the file on disk
+        let root = std::env::current_dir().unwrap();
+        let uri = Url::from_file_path(root.join("CHANGELOG.md")).unwrap();
+        let insert = TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(0, 0), Position::new(0, 0))),
+            range_length: None,
+            text: "Prefix ".to_string(),
+        };
+        state.apply_change(&uri, insert.clone());
+        let on_disk = fs::read_to_string(root.join("CHANGELOG.md")).unwrap();
+        assert_eq!(
+            state.buffer_for_uri(&uri),
+            Some(format!("Prefix {on_disk}").as_str())
+        );
+        let missing = Url::from_file_path(root.join("missing.md")).unwrap();
+        state.apply_change(&missing, insert);
+        assert_eq!(state.buffer_for_uri(&missing), None);
+    }
+
+    #[test]
+    fn test_files_linking_to() {
+        let mut state = init_state();
+        let root = std::env::current_dir().unwrap();
+        let uri_a = Url::from_file_path(root.join("a.md")).unwrap();
+        let uri_b = Url::from_file_path(root.join("b.md")).unwrap();
+        state.set_buffer(&uri_a, "# A\n\n[to b](/b.md)\n".to_string());
+        assert!(state.files_linking_to(&uri_b).is_empty());
+        state.open_file(&uri_b, "# B\n".to_string());
+        assert_eq!(state.files_linking_to(&uri_b), vec![uri_a.clone()]);
+        // opened files are managed by the editor
+        state.remove_file(&uri_b);
+        assert!(state.md_files.contains_key(&uri_b));
+        state.remove_file(&uri_a);
+        assert!(!state.md_files.contains_key(&uri_a));
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let root = std::env::current_dir().unwrap();
+        assert!(is_ignored(&root, &root.join("target/debug/notes.md")));
+        assert!(is_ignored(&root, &root.join(".git/HEAD")));
+        assert!(is_ignored(&root, &root.join("Cargo.lock")));
+        assert!(!is_ignored(&root, &root.join("src/main.rs")));
+        assert!(!is_ignored(&root, &root.join("README.md")));
+        // agrees with indexing
+        let state = init_state();
+        for file in state.find_files(&[create_workspacefolder()]) {
+            assert!(!is_ignored(&root, &file), "{}", file.display());
+        }
+        // outside of the workspace folder
+        assert!(!is_ignored(
+            &root.join("src"),
+            &root.join("target/notes.md")
+        ));
+    }
+
+    #[test]
+    fn test_multiple_workspace_folders() {
+        let mut state = State::new();
+        let root = std::env::current_dir().unwrap();
+        for name in ["docs", "api"] {
+            state.add_workspace_folder(WorkspaceFolder {
+                uri: Url::from_file_path(root.join(name)).unwrap(),
+                name: name.to_string(),
+            });
+        }
+        let docs_uri = Url::from_file_path(root.join("docs/index.md")).unwrap();
+        let api_uri = Url::from_file_path(root.join("api/index.md")).unwrap();
+        let api_other_uri = Url::from_file_path(root.join("api/other.md")).unwrap();
+        state.set_buffer(&docs_uri, "[to other](/other.md)\n".to_string());
+        state.set_buffer(&api_uri, "[to other](/other.md)\n".to_string());
+        state.set_buffer(&api_other_uri, "# Other\n".to_string());
+        assert_eq!(
+            state.workspace_root_for_uri(&api_uri),
+            Some(root.join("api"))
+        );
+        assert_eq!(
+            state.files_linking_to(&api_other_uri),
+            vec![api_uri.clone()]
+        );
+
+        let removed =
+            state.remove_workspace_folder(&Url::from_file_path(root.join("api")).unwrap());
+        assert_eq!(removed.len(), 2);
+        assert!(state.md_files.contains_key(&docs_uri));
+    }
+
     #[test]
     fn test_index_md_files() {
         let workspace_folder = create_workspacefolder();
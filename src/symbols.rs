@@ -2,7 +2,6 @@ use lsp_types::{DocumentSymbol, DocumentSymbolParams, Location, SymbolKind, Work
 
 use crate::{
     ast::{get_heading_text, TraverseNode},
-    definition::range_from_position,
     state::State,
 };
 
@@ -11,16 +10,16 @@ pub fn document_symbols(
     state: &State,
 ) -> Option<Vec<DocumentSymbol>> {
     let req_uri = &params.text_document.uri;
-    let req_ast = state.ast_for_uri(req_uri)?;
-    let headings = req_ast.find_headings();
+    let req_md_file = state.md_files.get(req_uri)?;
+    let headings = req_md_file.ast.find_headings();
 
     headings
         .into_iter()
         .map(|heading| {
             get_heading_text(heading).and_then(|heading_text| {
                 heading.position.as_ref().map(|pos| {
-                    let range = range_from_position(pos);
-                    let name = add_pounds(heading_text, heading.depth);
+                    let range = req_md_file.range(pos);
+                    let name = add_pounds(&heading_text, heading.depth);
                     #[allow(deprecated)] // TODO: don't know how else
                     DocumentSymbol {
                         name,
@@ -48,8 +47,8 @@ pub fn workspace_symbols(state: &State) -> Option<Vec<WorkspaceSymbol>> {
             headings.into_iter().map(|heading| {
                 get_heading_text(heading).and_then(|heading_text| {
                     heading.position.as_ref().map(|pos| {
-                        let range = range_from_position(pos);
-                        let name = add_pounds(heading_text, heading.depth);
+                        let range = md_file.range(pos);
+                        let name = add_pounds(&heading_text, heading.depth);
                         let location = Location {
                             uri: url.clone(),
                             range,
@@ -0,0 +1,298 @@
+use markdown::mdast::{self, AlignKind};
+use unicode_width::UnicodeWidthStr;
+
+/// Contents of a GFM table, that can be edited and rendered with consistent
+/// padding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    /// Header row and body rows, the delimiter row is described by `align`
+    pub rows: Vec<Vec<String>>,
+    pub align: Vec<AlignKind>,
+}
+
+impl Table {
+    /// Contents of `table` in `buffer`. Missing cells are added, excess cells
+    /// (ignored by GFM) are kept in the last cell of the row.
+    pub fn new(table: &mdast::Table, buffer: &str) -> Self {
+        let columns = table.align.len();
+        let rows = table
+            .children
+            .iter()
+            .map(|row| {
+                let mut cells: Vec<String> = row
+                    .children()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|cell| cell.position())
+                    .map(|pos| cell_text(&buffer[pos.start.offset..pos.end.offset]))
+                    .collect();
+                if cells.len() > columns {
+                    let excess = cells.split_off(columns - 1);
+                    cells.push(excess.join(" | "));
+                }
+                cells.resize(columns, String::new());
+                cells
+            })
+            .collect();
+        Self {
+            rows,
+            align: table.align.clone(),
+        }
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.align.len()
+    }
+
+    pub fn insert_column(&mut self, column: usize) {
+        self.align.insert(column, AlignKind::None);
+        for row in &mut self.rows {
+            row.insert(column, String::new());
+        }
+    }
+
+    pub fn delete_column(&mut self, column: usize) {
+        self.align.remove(column);
+        for row in &mut self.rows {
+            row.remove(column);
+        }
+    }
+
+    pub fn swap_columns(&mut self, a: usize, b: usize) {
+        self.align.swap(a, b);
+        for row in &mut self.rows {
+            row.swap(a, b);
+        }
+    }
+
+    /// Inserts an empty body row, `row` 0 is the header
+    pub fn insert_row(&mut self, row: usize) {
+        self.rows
+            .insert(row, vec![String::new(); self.column_count()]);
+    }
+
+    pub fn delete_row(&mut self, row: usize) {
+        self.rows.remove(row);
+    }
+
+    pub fn align_column(&mut self, column: usize, align: AlignKind) {
+        self.align[column] = align;
+    }
+
+    /// The table as markdown, all lines but the first start with `prefix`, the
+    /// indentation or `> ` of the containing blocks
+    pub fn render(&self, prefix: &str) -> String {
+        let widths: Vec<usize> = (0..self.column_count())
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .map(|row| cell_width(&row[column]))
+                    .fold(3, usize::max)
+            })
+            .collect();
+        let delimiter_row: Vec<String> = self
+            .align
+            .iter()
+            .zip(&widths)
+            .map(|(align, &width)| match align {
+                AlignKind::None => "-".repeat(width),
+                AlignKind::Left => format!(":{}", "-".repeat(width - 1)),
+                AlignKind::Right => format!("{}:", "-".repeat(width - 1)),
+                AlignKind::Center => format!(":{}:", "-".repeat(width - 2)),
+            })
+            .collect();
+        let mut lines: Vec<String> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&self.align)
+                    .zip(&widths)
+                    .map(|((cell, align), &width)| pad(cell, *align, width))
+                    .collect();
+                format!("| {} |", cells.join(" | "))
+            })
+            .collect();
+        lines.insert(1, format!("| {} |", delimiter_row.join(" | ")));
+        lines.join(&format!("\n{prefix}"))
+    }
+}
+
+/// `table` realigned, `None` if it is already formatted
+pub fn format_table(table: &mdast::Table, buffer: &str) -> Option<String> {
+    let pos = table.position.as_ref()?;
+    let formatted = Table::new(table, buffer).render(&table_prefix(table, buffer));
+    (buffer.get(pos.start.offset..pos.end.offset)? != formatted).then_some(formatted)
+}
+
+/// Prefix for the lines of `table` after the first one, the indentation or
+/// `> ` of the containing blocks
+pub fn table_prefix(table: &mdast::Table, buffer: &str) -> String {
+    let Some(start) = table.position.as_ref().map(|pos| pos.start.offset) else {
+        return String::new();
+    };
+    let line_start = buffer[..start].rfind('\n').map_or(0, |i| i + 1);
+    // list markers on the first line are indentation on the following ones
+    buffer[line_start..start]
+        .chars()
+        .map(|c| match c {
+            '>' | '\t' => c,
+            _ => ' ',
+        })
+        .collect()
+}
+
+/// Row (`None` for the delimiter row) and column of the cell of `table` at
+/// `offset`
+pub fn cell_at_offset(
+    table: &mdast::Table,
+    buffer: &str,
+    offset: usize,
+) -> Option<(Option<usize>, usize)> {
+    let start = table.position.as_ref()?.start.offset;
+    let line = buffer.get(start..offset)?.matches('\n').count();
+    let row = match line {
+        0 => Some(0),
+        1 => None,
+        line => Some(line - 1),
+    };
+    let line_start = match line {
+        0 => start,
+        _ => buffer[..offset].rfind('\n').map_or(0, |i| i + 1),
+    };
+    let before_cursor = buffer
+        .get(line_start..offset)?
+        .trim_start_matches(|c: char| c == '>' || c.is_whitespace());
+    let pipes = unescaped_pipes(before_cursor);
+    // a leading pipe doesn't start a new cell
+    let column = match before_cursor.starts_with('|') {
+        true => pipes.saturating_sub(1),
+        false => pipes,
+    };
+    Some((row, column.min(table.align.len().saturating_sub(1))))
+}
+
+fn unescaped_pipes(text: &str) -> usize {
+    let mut escaped = false;
+    let mut pipes = 0;
+    for c in text.chars() {
+        match c {
+            '|' if !escaped => pipes += 1,
+            '\\' => {
+                escaped = !escaped;
+                continue;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    pipes
+}
+
+/// Content of a cell, without its pipes and surrounding whitespace
+fn cell_text(raw: &str) -> String {
+    let text = raw.trim();
+    let text = text.strip_prefix('|').unwrap_or(text);
+    let text = match text.strip_suffix('|') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => text,
+    };
+    text.trim().to_string()
+}
+
+/// Columns `text` takes up in the editor, wide characters (CJK, emoji) take
+/// up two
+fn cell_width(text: &str) -> usize {
+    text.width()
+}
+
+fn pad(text: &str, align: AlignKind, width: usize) -> String {
+    let padding = width.saturating_sub(cell_width(text));
+    let (left, right) = match align {
+        AlignKind::Right => (padding, 0),
+        AlignKind::Center => (padding / 2, padding - padding / 2),
+        AlignKind::Left | AlignKind::None => (0, padding),
+    };
+    format!("{}{text}{}", " ".repeat(left), " ".repeat(right))
+}
+
+#[cfg(test)]
+mod tests {
+    use markdown::mdast::Node;
+
+    use super::*;
+
+    fn parse_table(md: &str) -> mdast::Table {
+        let ast = markdown::to_mdast(md, &markdown::ParseOptions::gfm()).unwrap();
+        ast.children()
+            .into_iter()
+            .flatten()
+            .find_map(|node| match node {
+                Node::Table(table) => Some(table.clone()),
+                Node::Blockquote(quote) => quote.children.iter().find_map(|node| match node {
+                    Node::Table(table) => Some(table.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_table_render() {
+        let md =
+            "| Name | Value \\| unit |\n|:-|-:|\n| a | 1 |\n| long name |\n| b | 2 | excess |\n";
+        let mut table = Table::new(&parse_table(md), md);
+        assert_eq!(
+            table.rows,
+            vec![
+                vec!["Name", "Value \\| unit"],
+                vec!["a", "1"],
+                vec!["long name", ""],
+                vec!["b", "2 | excess"],
+            ]
+        );
+        table.align_column(1, AlignKind::Center);
+        assert_eq!(
+            table.render("> "),
+            "| Name      | Value \\| unit |\n\
+             > | :-------- | :-----------: |\n\
+             > | a         |       1       |\n\
+             > | long name |               |\n\
+             > | b         |  2 | excess   |"
+        );
+    }
+
+    #[test]
+    fn test_cell_at_offset() {
+        let md = "> | a | b \\| c |\n> | - | - |\n> | x | y |\n";
+        let table = parse_table(md);
+        assert_eq!(table_prefix(&table, md), "> ");
+        let offset = |line: usize, character: usize| {
+            md.split_inclusive('\n')
+                .take(line)
+                .map(str::len)
+                .sum::<usize>()
+                + character
+        };
+        assert_eq!(cell_at_offset(&table, md, offset(0, 4)), Some((Some(0), 0)));
+        assert_eq!(
+            cell_at_offset(&table, md, offset(0, 12)),
+            Some((Some(0), 1))
+        );
+        assert_eq!(cell_at_offset(&table, md, offset(1, 9)), Some((None, 1)));
+        assert_eq!(cell_at_offset(&table, md, offset(2, 2)), Some((Some(1), 0)));
+    }
+
+    #[test]
+    fn test_format_table() {
+        let md = "| 名前 | emoji |\n|-|:-:|\n| a | 🦀 |\n";
+        assert_eq!(
+            format_table(&parse_table(md), md).as_deref(),
+            Some("| 名前 | emoji |\n| ---- | :---: |\n| a    |  🦀   |")
+        );
+        let formatted = "| a   |\n| --- |\n| b   |\n";
+        assert_eq!(format_table(&parse_table(formatted), formatted), None);
+    }
+}